@@ -0,0 +1,366 @@
+// biome.rs
+use crate::builder::*;
+use crate::density::PERLIN_SLOPE;
+use crate::octree::*;
+use crate::types::*;
+
+use glam::{DVec2, UVec2, UVec3};
+use noise::{Fbm, MultiFractal, NoiseFn, Perlin};
+
+// Something placed on top of the surface, a column of height voxels of material
+#[derive(Debug, Clone, PartialEq)]
+pub struct Decoration {
+    pub material: u16,
+    pub height: u32,
+    // Share of the surface columns of the biome that get this decoration
+    pub chance: f64,
+}
+
+// A kind of landscape. Every biome sits at a point of the climate space, a column belongs to
+// the biome whose point is closest to the temperature and humidity there.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Biome {
+    pub name: String,
+    pub temperature: f64,
+    pub humidity: f64,
+    // Height of the surface where the terrain noise is 0 and its largest distance from it
+    pub base_height: f64,
+    pub amplitude: f64,
+    // The top voxel of a column and the ones right below it
+    pub surface: u16,
+    pub subsurface: u16,
+    pub subsurface_depth: u32,
+    // Tried in order, the chances of a biome should add up to at most 1
+    pub decorations: Vec<Decoration>,
+}
+
+impl Biome {
+    fn climate(&self) -> DVec2 {
+        DVec2::new(self.temperature, self.humidity)
+    }
+
+    fn decoration_height(&self) -> u32 {
+        self.decorations.iter().map(|d| d.height).max().unwrap_or(0)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BiomeSettings {
+    pub seed: u32,
+    pub biomes: Vec<Biome>,
+    // Frequency of the first climate octave in cycles per voxel, every further octave doubles
+    // it at half the amplitude
+    pub climate_frequency: f64,
+    pub climate_octaves: usize,
+    // Distance in climate space over which neighbouring biomes are blended, a biome whose
+    // point is farther than this behind the closest one has no say in a column
+    pub blend: f64,
+    // Slope of Perlin noise the climate bounds of an octant assume, see PERLIN_SLOPE
+    pub perlin_slope: f64,
+    pub terrain_frequency: f64,
+    pub terrain_octaves: usize,
+    pub air: u16,
+    pub stone: u16,
+}
+
+impl Default for BiomeSettings {
+    fn default() -> Self {
+        let biome = |name: &str, temperature, humidity, base_height, amplitude, surface, subsurface, decorations| Biome {
+            name: name.to_string(),
+            temperature,
+            humidity,
+            base_height,
+            amplitude,
+            surface,
+            subsurface,
+            subsurface_depth: 3,
+            decorations,
+        };
+        Self {
+            seed: 0,
+            biomes: vec![
+                biome("plains", 0.0, 0.0, 64.0, 6.0, 2, 3, vec![Decoration { material: 8, height: 1, chance: 0.05 }]),
+                biome("forest", 0.0, 0.6, 68.0, 12.0, 2, 3, vec![Decoration { material: 7, height: 6, chance: 0.03 }]),
+                biome("desert", 0.7, -0.6, 62.0, 8.0, 4, 4, vec![Decoration { material: 9, height: 3, chance: 0.005 }]),
+                biome("tundra", -0.7, -0.2, 66.0, 10.0, 5, 3, Vec::new()),
+                biome("mountains", -0.3, 0.5, 90.0, 40.0, 1, 1, Vec::new()),
+            ],
+            climate_frequency: 1.0 / 512.0,
+            climate_octaves: 2,
+            blend: 0.25,
+            perlin_slope: PERLIN_SLOPE,
+            terrain_frequency: 1.0 / 128.0,
+            terrain_octaves: 5,
+            air: 0,
+            stone: 1,
+        }
+    }
+}
+
+// The biomes that can have a say somewhere in an octant. It is narrowed down from the root
+// and handed to the children, once a single biome is left its columns are built without
+// looking at the climate or blending anything.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BiomeState {
+    candidates: u64,
+}
+
+impl BiomeState {
+    pub fn contains(&self, biome: usize) -> bool {
+        self.candidates >> biome & 1 != 0
+    }
+
+    pub fn single(&self) -> Option<usize> {
+        (self.candidates.count_ones() == 1).then(|| self.candidates.trailing_zeros() as usize)
+    }
+
+    fn iter(self) -> impl Iterator<Item = usize> {
+        (0..64).filter(move |&i| self.contains(i))
+    }
+}
+
+// What a single column of the terrain looks like
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BiomeColumn {
+    pub height: i32,
+    // The biome with the largest weight, it decides the materials
+    pub biome: usize,
+}
+
+// Builds terrain from temperature and humidity noise. Every column blends the heights of the
+// biomes close to its climate, weighted by how close they are, and takes its materials and
+// decorations from the closest one. y is up.
+pub struct BiomeBuilder {
+    settings: BiomeSettings,
+    tree_depth: u32,
+    temperature: Vec<Perlin>,
+    humidity: Vec<Perlin>,
+    terrain: Fbm<Perlin>,
+    // Sum of the climate octave amplitudes, the climate noise is divided by it
+    norm: f64,
+    // Largest change of the climate per voxel on either axis
+    slope: f64,
+}
+
+impl BiomeBuilder {
+    pub fn new(tree_depth: u32, settings: BiomeSettings) -> Self {
+        assert!(tree_depth > 0 && tree_depth <= MAX_TREE_DEPTH, "The tree depth has to be between 1 and {}.", MAX_TREE_DEPTH);
+        assert!(!settings.biomes.is_empty() && settings.biomes.len() <= 64, "There have to be between 1 and 64 biomes.");
+        assert!(settings.climate_octaves > 0, "The climate needs at least one octave.");
+        assert!(settings.blend > 0.0, "The blend distance has to be positive.");
+        assert!(settings.perlin_slope > 0.0, "The Perlin slope has to be positive.");
+
+        let octaves = |offset: u32| (0..settings.climate_octaves as u32).map(|i| Perlin::new(settings.seed.wrapping_add(offset + i))).collect();
+        let temperature = octaves(0x1000);
+        let humidity = octaves(0x2000);
+        let terrain = Fbm::<Perlin>::new(settings.seed).set_octaves(settings.terrain_octaves).set_frequency(settings.terrain_frequency);
+
+        let norm = (0..settings.climate_octaves).map(|i| 0.5f64.powi(i as i32)).sum::<f64>();
+        let slope = settings.climate_frequency * settings.climate_octaves as f64 * settings.perlin_slope / norm;
+
+        Self { settings, tree_depth, temperature, humidity, terrain, norm, slope }
+    }
+
+    pub fn settings(&self) -> &BiomeSettings {
+        &self.settings
+    }
+
+    pub fn biome(&self, index: usize) -> &Biome {
+        &self.settings.biomes[index]
+    }
+
+    // Temperature and humidity at a point of the xz plane
+    pub fn climate(&self, point: DVec2) -> DVec2 {
+        let sample = |octaves: &[Perlin]| {
+            let mut value = 0.0;
+            let mut amplitude = 1.0;
+            let mut p = point * self.settings.climate_frequency;
+            for octave in octaves {
+                value += octave.get(p.to_array()) * amplitude;
+                amplitude *= 0.5;
+                p *= 2.0;
+            }
+            value / self.norm
+        };
+        DVec2::new(sample(&self.temperature), sample(&self.humidity))
+    }
+
+    pub fn column(&self, column: UVec2) -> BiomeColumn {
+        self.column_of(column, self.default_state())
+    }
+
+    fn column_of(&self, column: UVec2, state: BiomeState) -> BiomeColumn {
+        let noise = self.terrain.get(column.as_dvec2().to_array()).clamp(-1.0, 1.0);
+        let height = |biome: &Biome| biome.base_height + biome.amplitude * noise;
+
+        if let Some(biome) = state.single() {
+            return BiomeColumn { height: height(self.biome(biome)).round() as i32, biome };
+        }
+
+        let climate = self.climate(column.as_dvec2());
+        let distances: Vec<(usize, f64)> = state.iter().map(|i| (i, self.biome(i).climate().distance(climate))).collect();
+        let closest = distances.iter().fold((0, f64::INFINITY), |a, &b| if b.1 < a.1 { b } else { a });
+
+        let (mut sum, mut weights) = (0.0, 0.0);
+        for &(i, distance) in &distances {
+            let weight = (1.0 - (distance - closest.1) / self.settings.blend).max(0.0).powi(2);
+            sum += height(self.biome(i)) * weight;
+            weights += weight;
+        }
+        BiomeColumn { height: (sum / weights).round() as i32, biome: closest.0 }
+    }
+
+    // The decoration on top of a column, if it has one
+    fn decoration<'a>(&self, column: UVec2, biome: &'a Biome) -> Option<&'a Decoration> {
+        let mut hash = (self.settings.seed as u64) << 42 ^ (column.x as u64) << 21 ^ column.y as u64;
+        hash = (hash ^ hash >> 30).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        hash = (hash ^ hash >> 27).wrapping_mul(0x94d0_49bb_1331_11eb);
+        hash ^= hash >> 31;
+        let mut roll = (hash >> 11) as f64 / (1u64 << 53) as f64;
+
+        biome.decorations.iter().find(|d| {
+            roll -= d.chance;
+            roll < 0.0
+        })
+    }
+
+    pub fn material(&self, pos: UVec3) -> u16 {
+        self.material_of(pos, self.default_state())
+    }
+
+    fn material_of(&self, pos: UVec3, state: BiomeState) -> u16 {
+        let column = UVec2::new(pos.x, pos.z);
+        let BiomeColumn { height, biome } = self.column_of(column, state);
+        let biome = self.biome(biome);
+        match height as i64 - pos.y as i64 {
+            d if d < 0 => match self.decoration(column, biome) {
+                Some(decoration) if -d <= decoration.height as i64 => decoration.material,
+                _ => self.settings.air,
+            },
+            0 => biome.surface,
+            d if d <= biome.subsurface_depth as i64 => biome.subsurface,
+            _ => self.settings.stone,
+        }
+    }
+
+    // Drops the biomes that have no weight anywhere in the footprint of the octant
+    fn narrow(&self, pos: &OctreeCreationPosition, state: BiomeState) -> BiomeState {
+        if state.single().is_some() {
+            return state;
+        }
+
+        let extent = (pos.size() - 1) as f64 / 2.0;
+        let center = DVec2::new(pos.position().x as f64, pos.position().z as f64) + DVec2::splat(extent);
+        let climate = self.climate(center);
+        // How far the climate can move away from the one at the center, both of its axes move
+        // by at most the slope times the distance to a corner column
+        let reach = self.slope * extent * 2.0;
+
+        let distance = |i: usize| self.biome(i).climate().distance(climate);
+        let closest = state.iter().map(distance).fold(f64::INFINITY, f64::min);
+        let candidates = state.iter()
+            .filter(|&i| distance(i) - closest - 2.0 * reach < self.settings.blend)
+            .fold(0, |mask, i| mask | 1 << i);
+        BiomeState { candidates }
+    }
+}
+
+impl OctreeBuilder<BiomeState> for BiomeBuilder {
+    fn default_state(&self) -> BiomeState {
+        BiomeState { candidates: u64::MAX >> (64 - self.settings.biomes.len()) }
+    }
+
+    fn get_tree_depth(&self) -> u32 {
+        self.tree_depth
+    }
+
+    fn get_octant(&self, pos: &OctreeCreationPosition, _buffer: &mut UnmanagedByteBuffer, state: &BiomeState) -> OctreeBuilderResult<BiomeState> {
+        if pos.level() == 0 {
+            return OctreeBuilderResult::Homogeneous(self.material_of(pos.position(), *state));
+        }
+
+        let state = self.narrow(pos, *state);
+        // A blended height lies between the heights of the biomes it is blended from, but the
+        // subsurface and decorations on top of it can come from any one of them
+        let biomes = || state.iter().map(|i| self.biome(i));
+        let lowest = biomes().map(|b| (b.base_height - b.amplitude).floor() as i64).min().unwrap()
+            - biomes().map(|b| b.subsurface_depth as i64).max().unwrap();
+        let highest = biomes().map(|b| (b.base_height + b.amplitude).ceil() as i64).max().unwrap()
+            + biomes().map(|b| b.decoration_height() as i64).max().unwrap();
+        let (bottom, top) = (pos.position().y as i64, pos.position().y as i64 + pos.size() as i64 - 1);
+
+        if bottom > highest {
+            OctreeBuilderResult::Homogeneous(self.settings.air)
+        } else if top < lowest {
+            OctreeBuilderResult::Homogeneous(self.settings.stone)
+        } else {
+            OctreeBuilderResult::SamplingRequired(state)
+        }
+    }
+
+    fn get_block(&self, pos: &OctreeCreationPosition, _buffer: &mut UnmanagedByteBuffer, state: &BiomeState) -> [u16; 8] {
+        VoxelOctant::ALL.map(|octant| self.material_of(pos.position() + octant.to_offset(), *state))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn biome(name: &str, temperature: f64, base_height: f64, subsurface_depth: u32, decorations: Vec<Decoration>) -> Biome {
+        Biome { name: name.to_string(), temperature, humidity: 0.0, base_height, amplitude: 4.0, surface: 2, subsurface: 3, subsurface_depth, decorations }
+    }
+
+    fn assert_matches_material(settings: BiomeSettings, tree_depth: u32) {
+        let builder = BiomeBuilder::new(tree_depth, settings);
+        let tree = VoxelOctree::from_builder(&builder);
+        let size = 1 << tree_depth;
+        for x in 0..size {
+            for y in 0..size {
+                for z in 0..size {
+                    let pos = UVec3::new(x, y, z);
+                    assert_eq!(tree.get(pos), builder.material(pos), "at {}", pos);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn subsurface_of_any_candidate_reaches_below_blended_height() {
+        let settings = BiomeSettings {
+            biomes: vec![biome("a", -0.1, 40.0, 30, Vec::new()), biome("b", 0.1, 10.0, 0, Vec::new())],
+            climate_frequency: 1.0 / 32.0,
+            blend: 1.0,
+            ..Default::default()
+        };
+        assert_matches_material(settings, 6);
+    }
+
+    #[test]
+    fn decoration_of_any_candidate_reaches_above_blended_height() {
+        let tall = vec![Decoration { material: 7, height: 20, chance: 0.5 }];
+        let settings = BiomeSettings {
+            biomes: vec![
+                biome("low", 0.0, 10.0, 3, tall),
+                biome("b", 0.3, 40.0, 3, Vec::new()),
+                biome("c", -0.3, 40.0, 3, Vec::new()),
+                biome("d", 0.6, 40.0, 3, Vec::new()),
+            ],
+            climate_frequency: 1.0 / 32.0,
+            blend: 1.0,
+            ..Default::default()
+        };
+        assert_matches_material(settings, 6);
+    }
+
+    #[test]
+    fn default_biomes_match_material() {
+        let defaults = BiomeSettings::default();
+        let biomes: Vec<Biome> = defaults.biomes.iter().cloned().map(|b| Biome { base_height: b.base_height - 40.0, ..b }).collect();
+        for seed in 0..3 {
+            assert_matches_material(BiomeSettings { seed, biomes: biomes.clone(), climate_frequency: 1.0 / 96.0, ..BiomeSettings::default() }, 6);
+        }
+        let perlin_slope = crate::density::PERLIN_SLOPE_BOUND;
+        assert_matches_material(BiomeSettings { biomes, climate_frequency: 1.0 / 96.0, perlin_slope, ..BiomeSettings::default() }, 6);
+    }
+}
\ No newline at end of file
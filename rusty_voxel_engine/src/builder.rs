@@ -2,32 +2,38 @@
 
 use crate::types::*;
 
-use std::simd::*;
+use glam::UVec3;
 
-pub trait OctreeBuilder<T> {
+// Samples the voxels of a tree top down. T is the state handed from an octant to its
+// children, P the payload stored in the voxels.
+pub trait OctreeBuilder<T, P: VoxelPayload = u16> {
     fn default_state(&self) -> T;
     fn get_tree_depth(&self) -> u32;
-    fn get_octant (&self, pos: &OctreeCreationPosition, buffer: &mut UnmanagedByteBuffer, state: &T) -> OctreeBuilderResult<T>; 
-    fn get_block(&self, pos: &OctreeCreationPosition, buffer: &mut UnmanagedByteBuffer, state: &T) -> u16x8;
+    fn get_octant (&self, pos: &OctreeCreationPosition, buffer: &mut UnmanagedByteBuffer, state: &T) -> OctreeBuilderResult<T, P>;
+    fn get_block(&self, pos: &OctreeCreationPosition, buffer: &mut UnmanagedByteBuffer, state: &T) -> [P; 8];
 }
 
-pub enum OctreeBuilderResult<T> {
-    Homogeneous(u16),
+pub enum OctreeBuilderResult<T, P = u16> {
+    // The whole octant is filled with a single material, nothing below it gets sampled
+    Homogeneous(P),
+    // The octant has to be subdivided, the children are built with the same state
     Sparse,
+    // The octant has to be subdivided, the children are built with the given state
     SamplingRequired(T),
 }
+
 pub struct OctreeCreationPosition {
-    position: glam::Vec3,
+    position: UVec3,
     level: u32,
     octant: VoxelOctant,
 }
 
 impl OctreeCreationPosition {
-    pub fn new(position: glam::Vec3, level: u32, octant: VoxelOctant) -> Self {
+    pub fn new(position: UVec3, level: u32, octant: VoxelOctant) -> Self {
         Self { position, level, octant }
     }
 
-    pub fn position(&self) -> glam::Vec3 {
+    pub fn position(&self) -> UVec3 {
         self.position
     }
 
@@ -39,35 +45,114 @@ impl OctreeCreationPosition {
         self.octant
     }
 
+    // Edge length of the octant in voxels
+    pub fn size(&self) -> u32 {
+        1 << self.level
+    }
+
+    /// # Safety
+    /// The octant has to be above level 0.
     pub unsafe fn child_unchecked(&self, octant: VoxelOctant) -> OctreeCreationPosition {
         debug_assert!(self.level > 0);
-        Self::new(self.position + (octant.into() << self.level), self.level - 1, octant)
+        Self::new(self.position + (octant.to_offset() << (self.level - 1)), self.level - 1, octant)
     }
 }
 
-pub struct Array3DOctreeBuilder<'a> {
-    array: &'a multiarray::Array3D<u16>,
-    default_material: u16,
+pub struct Array3DOctreeBuilder<'a, P: VoxelPayload = u16> {
+    array: &'a multiarray::Array3D<P>,
+    default_material: P,
     tree_depth: u32,
 }
 
-impl<'a> Array3DOctreeBuilder<'a> {
-    pub fn new(array: &'a multiarray::Array3D<u16>, default_material: u16) -> Self {
+impl<'a, P: VoxelPayload> Array3DOctreeBuilder<'a, P> {
+    pub fn new(array: &'a multiarray::Array3D<P>, default_material: P) -> Self {
         let ext = array.extents();
         let size = std::cmp::max(ext[0], std::cmp::max(ext[1], ext[2])) as u32;
-        Self { array, default_material, tree_depth: size }
+        // The tree has to cover the largest extent, voxels outside the array get the default material
+        let tree_depth = std::cmp::max(size.next_power_of_two().trailing_zeros(), 1);
+        Self { array, default_material, tree_depth }
+    }
+
+    fn get_block_continuous(&self, pos: UVec3) -> [P; 8] {
+        read_array_block(self.array, self.default_material, pos)
+    }
+}
+
+impl<'a, P: VoxelPayload> OctreeBuilder<(), P> for Array3DOctreeBuilder<'a, P> {
+    fn default_state(&self) {}
+
+    fn get_tree_depth(&self) -> u32 {
+        self.tree_depth
+    }
+
+    fn get_octant(&self, pos: &OctreeCreationPosition, _buffer: &mut UnmanagedByteBuffer, _state: &()) -> OctreeBuilderResult<(), P> {
+        if array_contains_octant(self.array, pos) {
+            OctreeBuilderResult::Sparse
+        } else {
+            OctreeBuilderResult::Homogeneous(self.default_material)
+        }
+    }
+
+    fn get_block(&self, pos: &OctreeCreationPosition, _buffer: &mut UnmanagedByteBuffer, _state: &()) -> [P; 8] {
+        self.get_block_continuous(pos.position())
+    }
+}
+
+// Builds a tree covering a whole Array3D2P. With the Morton layout every 2x2x2 block is
+// stored in VoxelOctant order and gets copied as a single slice.
+pub struct Array3D2POctreeBuilder<'a, P: VoxelPayload = u16> {
+    array: &'a Array3D2P<P>,
+    tree_depth: u32,
+}
+
+impl<'a, P: VoxelPayload> Array3D2POctreeBuilder<'a, P> {
+    pub fn new(array: &'a Array3D2P<P>) -> Self {
+        assert!(array.size() > 1, "The array has to be at least 2 voxels wide.");
+        Self { array, tree_depth: array.size().trailing_zeros() }
+    }
+}
+
+impl<'a, P: VoxelPayload> OctreeBuilder<(), P> for Array3D2POctreeBuilder<'a, P> {
+    fn default_state(&self) {}
+
+    fn get_tree_depth(&self) -> u32 {
+        self.tree_depth
+    }
+
+    fn get_octant(&self, _pos: &OctreeCreationPosition, _buffer: &mut UnmanagedByteBuffer, _state: &()) -> OctreeBuilderResult<(), P> {
+        OctreeBuilderResult::Sparse
+    }
+
+    fn get_block(&self, pos: &OctreeCreationPosition, _buffer: &mut UnmanagedByteBuffer, _state: &()) -> [P; 8] {
+        match self.array.layout() {
+            Array3DLayout::Morton => {
+                let start = self.array.index_of(pos.position());
+                self.array.as_slice()[start..start + 8].try_into().unwrap()
+            }
+            Array3DLayout::Linear => std::array::from_fn(|i| *self.array.get(pos.position() + VoxelOctant::from_index(i).to_offset())),
+        }
     }
+}
 
-    fn get_block_continuous(&self, pos: glam::UVec3) -> [u16; 8] {
-        let mut data: [u16; 8] = unsafe { std::mem::MaybeUninit::uninit().assume_init() };
-        let (x, y, z) = (pos.x as usize, pos.y as usize, pos.z as usize);
-        data[0] = self.array[[x, y, z]];
-        data[1] = self.array[[x + 1, y, z]];
-        data[2] = self.array[[x, y + 1, z]];
-        data[3] = self.array[[x, y, z + 1]];
-        data[4] = self.array[[x + 1, y, z + 1]];
-        data[5] = self.array[[x, y + 1, z + 1]];
-        data[6] = self.array[[x + 1, y + 1, z]];
-        data[7] = self .array[[x + 1, y + 1, z + 1]];
+// Checks whether any voxel of the octant lies inside the array
+pub(crate) fn array_contains_octant<P>(array: &multiarray::Array3D<P>, pos: &OctreeCreationPosition) -> bool {
+    let ext = array.extents();
+    let p = pos.position();
+    (p.x as usize) < ext[0] && (p.y as usize) < ext[1] && (p.z as usize) < ext[2]
+}
+
+// Reads the 2x2x2 block at pos in VoxelOctant order, voxels outside the array read as default_material
+pub(crate) fn read_array_block<P: Copy>(array: &multiarray::Array3D<P>, default_material: P, pos: UVec3) -> [P; 8] {
+    let ext = array.extents();
+    let mut data = [default_material; 8];
+
+    for octant in VoxelOctant::iter_all() {
+        let p = pos + octant.to_offset();
+        let (x, y, z) = (p.x as usize, p.y as usize, p.z as usize);
+        if x < ext[0] && y < ext[1] && z < ext[2] {
+            data[octant.index()] = array[[x, y, z]];
+        }
     }
+
+    data
 }
\ No newline at end of file
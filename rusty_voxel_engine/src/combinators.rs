@@ -0,0 +1,589 @@
+// combinators.rs
+use crate::builder::*;
+use crate::octree::*;
+use crate::types::*;
+
+use glam::{I64Vec3, IVec3, UVec3};
+
+// Builders wrapping other builders. The state of a wrapped builder is carried as a
+// BuilderPart, once it reports a homogeneous octant it isn't asked about that octant's
+// children again. Wrapped states get cloned when a builder answers Sparse.
+
+// Lets combinators borrow their builders, so one builder can be part of several of them
+impl<T, P: VoxelPayload, B: OctreeBuilder<T, P> + ?Sized> OctreeBuilder<T, P> for &B {
+    fn default_state(&self) -> T {
+        (**self).default_state()
+    }
+
+    fn get_tree_depth(&self) -> u32 {
+        (**self).get_tree_depth()
+    }
+
+    fn get_octant(&self, pos: &OctreeCreationPosition, buffer: &mut UnmanagedByteBuffer, state: &T) -> OctreeBuilderResult<T, P> {
+        (**self).get_octant(pos, buffer, state)
+    }
+
+    fn get_block(&self, pos: &OctreeCreationPosition, buffer: &mut UnmanagedByteBuffer, state: &T) -> [P; 8] {
+        (**self).get_block(pos, buffer, state)
+    }
+}
+
+// Where a wrapped builder stands in the current octant
+#[derive(Debug, Clone, PartialEq)]
+pub enum BuilderPart<S, P> {
+    // The builder still has to be sampled, with this state
+    Sampling(S),
+    // The whole octant is filled with this material
+    Homogeneous(P),
+}
+
+impl<S: Clone, P: VoxelPayload> BuilderPart<S, P> {
+    // Asks builder about the octant at pos, returns the part for its children
+    fn step(&self, builder: &impl OctreeBuilder<S, P>, pos: &OctreeCreationPosition, buffer: &mut UnmanagedByteBuffer) -> Self {
+        match self {
+            BuilderPart::Homogeneous(mat) => BuilderPart::Homogeneous(*mat),
+            BuilderPart::Sampling(state) => match builder.get_octant(pos, buffer, state) {
+                OctreeBuilderResult::Homogeneous(mat) => BuilderPart::Homogeneous(mat),
+                OctreeBuilderResult::Sparse => BuilderPart::Sampling(state.clone()),
+                OctreeBuilderResult::SamplingRequired(child_state) => BuilderPart::Sampling(child_state),
+            },
+        }
+    }
+
+    // The 2x2x2 block at pos, self being the part get_octant returned for it
+    fn block(&self, builder: &impl OctreeBuilder<S, P>, pos: &OctreeCreationPosition, buffer: &mut UnmanagedByteBuffer) -> [P; 8] {
+        match self {
+            BuilderPart::Homogeneous(mat) => [*mat; 8],
+            BuilderPart::Sampling(state) => builder.get_block(pos, buffer, state),
+        }
+    }
+
+    fn material(&self) -> Option<P> {
+        match self {
+            BuilderPart::Homogeneous(mat) => Some(*mat),
+            BuilderPart::Sampling(_) => None,
+        }
+    }
+}
+
+// Stacks two builders, wherever top isn't empty it wins over bottom
+pub struct Layered<A, B, P: VoxelPayload = u16> {
+    top: A,
+    bottom: B,
+    empty: P,
+}
+
+impl<A, B, P: VoxelPayload> Layered<A, B, P> {
+    pub fn new(top: A, bottom: B, empty: P) -> Self {
+        Self { top, bottom, empty }
+    }
+}
+
+impl<SA: Clone, SB: Clone, P: VoxelPayload, A: OctreeBuilder<SA, P>, B: OctreeBuilder<SB, P>> OctreeBuilder<(BuilderPart<SA, P>, BuilderPart<SB, P>), P> for Layered<A, B, P> {
+    fn default_state(&self) -> (BuilderPart<SA, P>, BuilderPart<SB, P>) {
+        assert!(self.top.get_tree_depth() == self.bottom.get_tree_depth(), "Only builders of the same depth can be layered.");
+        (BuilderPart::Sampling(self.top.default_state()), BuilderPart::Sampling(self.bottom.default_state()))
+    }
+
+    fn get_tree_depth(&self) -> u32 {
+        self.top.get_tree_depth()
+    }
+
+    fn get_octant(&self, pos: &OctreeCreationPosition, buffer: &mut UnmanagedByteBuffer, state: &(BuilderPart<SA, P>, BuilderPart<SB, P>)) -> OctreeBuilderResult<(BuilderPart<SA, P>, BuilderPart<SB, P>), P> {
+        let top = state.0.step(&self.top, pos, buffer);
+        // A solid top hides the bottom builder entirely
+        match top.material() {
+            Some(mat) if mat != self.empty => return OctreeBuilderResult::Homogeneous(mat),
+            _ => {}
+        }
+
+        let bottom = state.1.step(&self.bottom, pos, buffer);
+        match (top.material(), bottom.material()) {
+            (Some(_), Some(mat)) => OctreeBuilderResult::Homogeneous(mat),
+            _ => OctreeBuilderResult::SamplingRequired((top, bottom)),
+        }
+    }
+
+    fn get_block(&self, pos: &OctreeCreationPosition, buffer: &mut UnmanagedByteBuffer, state: &(BuilderPart<SA, P>, BuilderPart<SB, P>)) -> [P; 8] {
+        let top = state.0.block(&self.top, pos, buffer);
+        let bottom = state.1.block(&self.bottom, pos, buffer);
+        std::array::from_fn(|i| if top[i] != self.empty { top[i] } else { bottom[i] })
+    }
+}
+
+// Keeps the voxels of inner only where mask isn't empty, everything else is empty
+pub struct Masked<A, M, P: VoxelPayload = u16> {
+    inner: A,
+    mask: M,
+    empty: P,
+}
+
+impl<A, M, P: VoxelPayload> Masked<A, M, P> {
+    pub fn new(inner: A, mask: M, empty: P) -> Self {
+        Self { inner, mask, empty }
+    }
+}
+
+impl<SA: Clone, SM: Clone, P: VoxelPayload, A: OctreeBuilder<SA, P>, M: OctreeBuilder<SM, P>> OctreeBuilder<(BuilderPart<SA, P>, BuilderPart<SM, P>), P> for Masked<A, M, P> {
+    fn default_state(&self) -> (BuilderPart<SA, P>, BuilderPart<SM, P>) {
+        assert!(self.inner.get_tree_depth() == self.mask.get_tree_depth(), "Only builders of the same depth can be masked.");
+        (BuilderPart::Sampling(self.inner.default_state()), BuilderPart::Sampling(self.mask.default_state()))
+    }
+
+    fn get_tree_depth(&self) -> u32 {
+        self.inner.get_tree_depth()
+    }
+
+    fn get_octant(&self, pos: &OctreeCreationPosition, buffer: &mut UnmanagedByteBuffer, state: &(BuilderPart<SA, P>, BuilderPart<SM, P>)) -> OctreeBuilderResult<(BuilderPart<SA, P>, BuilderPart<SM, P>), P> {
+        let mask = state.1.step(&self.mask, pos, buffer);
+        // Nothing of inner shows through an empty mask
+        if mask.material() == Some(self.empty) {
+            return OctreeBuilderResult::Homogeneous(self.empty);
+        }
+
+        let inner = state.0.step(&self.inner, pos, buffer);
+        match (inner.material(), mask.material()) {
+            (Some(mat), Some(_)) => OctreeBuilderResult::Homogeneous(mat),
+            _ => OctreeBuilderResult::SamplingRequired((inner, mask)),
+        }
+    }
+
+    fn get_block(&self, pos: &OctreeCreationPosition, buffer: &mut UnmanagedByteBuffer, state: &(BuilderPart<SA, P>, BuilderPart<SM, P>)) -> [P; 8] {
+        let mask = state.1.block(&self.mask, pos, buffer);
+        let inner = state.0.block(&self.inner, pos, buffer);
+        std::array::from_fn(|i| if mask[i] != self.empty { inner[i] } else { self.empty })
+    }
+}
+
+// Replaces every material of inner with what remap returns for it
+pub struct MaterialRemap<A, F> {
+    inner: A,
+    remap: F,
+}
+
+impl<A, F> MaterialRemap<A, F> {
+    pub fn new(inner: A, remap: F) -> Self {
+        Self { inner, remap }
+    }
+}
+
+impl<S, P: VoxelPayload, A: OctreeBuilder<S, P>, F: Fn(P) -> P> OctreeBuilder<S, P> for MaterialRemap<A, F> {
+    fn default_state(&self) -> S {
+        self.inner.default_state()
+    }
+
+    fn get_tree_depth(&self) -> u32 {
+        self.inner.get_tree_depth()
+    }
+
+    fn get_octant(&self, pos: &OctreeCreationPosition, buffer: &mut UnmanagedByteBuffer, state: &S) -> OctreeBuilderResult<S, P> {
+        match self.inner.get_octant(pos, buffer, state) {
+            OctreeBuilderResult::Homogeneous(mat) => OctreeBuilderResult::Homogeneous((self.remap)(mat)),
+            res => res,
+        }
+    }
+
+    fn get_block(&self, pos: &OctreeCreationPosition, buffer: &mut UnmanagedByteBuffer, state: &S) -> [P; 8] {
+        self.inner.get_block(pos, buffer, state).map(&self.remap)
+    }
+}
+
+// Keeps inner only inside the box between min and max (exclusive), the rest is filled with fill
+pub struct Clamp<A, P: VoxelPayload = u16> {
+    inner: A,
+    min: UVec3,
+    max: UVec3,
+    fill: P,
+}
+
+impl<A, P: VoxelPayload> Clamp<A, P> {
+    pub fn new(inner: A, min: UVec3, max: UVec3, fill: P) -> Self {
+        assert!(min.cmple(max).all(), "The box is inverted.");
+        Self { inner, min, max, fill }
+    }
+
+    fn inside(&self, pos: UVec3) -> bool {
+        pos.cmpge(self.min).all() && pos.cmplt(self.max).all()
+    }
+}
+
+impl<S: Clone, P: VoxelPayload, A: OctreeBuilder<S, P>> OctreeBuilder<BuilderPart<S, P>, P> for Clamp<A, P> {
+    fn default_state(&self) -> BuilderPart<S, P> {
+        BuilderPart::Sampling(self.inner.default_state())
+    }
+
+    fn get_tree_depth(&self) -> u32 {
+        self.inner.get_tree_depth()
+    }
+
+    fn get_octant(&self, pos: &OctreeCreationPosition, buffer: &mut UnmanagedByteBuffer, state: &BuilderPart<S, P>) -> OctreeBuilderResult<BuilderPart<S, P>, P> {
+        let (octant_min, octant_max) = (pos.position(), pos.position() + UVec3::splat(pos.size()));
+        if octant_max.cmple(self.min).any() || octant_min.cmpge(self.max).any() {
+            return OctreeBuilderResult::Homogeneous(self.fill);
+        }
+
+        let part = state.step(&self.inner, pos, buffer);
+        let covered = octant_min.cmpge(self.min).all() && octant_max.cmple(self.max).all();
+        match part.material() {
+            Some(mat) if covered => OctreeBuilderResult::Homogeneous(mat),
+            _ => OctreeBuilderResult::SamplingRequired(part),
+        }
+    }
+
+    fn get_block(&self, pos: &OctreeCreationPosition, buffer: &mut UnmanagedByteBuffer, state: &BuilderPart<S, P>) -> [P; 8] {
+        let block = state.block(&self.inner, pos, buffer);
+        std::array::from_fn(|i| if self.inside(pos.position() + VoxelOctant::from_index(i).to_offset()) { block[i] } else { self.fill })
+    }
+}
+
+// An octant of inner at min with edge length 1 << level, part is the part of inner for its
+// children. Homogeneous octants of inner are kept as they are, they stand for all of their
+// children.
+#[derive(Debug, Clone, PartialEq)]
+pub struct InnerOctant<S, P> {
+    min: UVec3,
+    level: u32,
+    part: BuilderPart<S, P>,
+}
+
+impl<S, P> InnerOctant<S, P> {
+    fn contains(&self, pos: UVec3) -> bool {
+        pos.cmpge(self.min).all() && pos.cmplt(self.min + UVec3::splat(1 << self.level)).all()
+    }
+}
+
+// State of Offset in an octant
+#[derive(Debug, Clone, PartialEq)]
+pub enum OffsetState<S, P> {
+    // The octant isn't fully inside of inner, nothing of inner has been sampled for it
+    Unknown,
+    // The octants of inner of the same size overlapping the octant
+    Inside(Vec<InnerOctant<S, P>>),
+}
+
+// Moves inner by offset voxels inside of a tree of tree_depth levels, voxels not covered by
+// inner are filled with fill. Unless the offset is a multiple of their size, octants don't line
+// up with the octants of inner and overlap up to eight of them, the octant is homogeneous when
+// all of those are.
+pub struct Offset<A, P: VoxelPayload = u16> {
+    inner: A,
+    offset: IVec3,
+    tree_depth: u32,
+    inner_depth: u32,
+    fill: P,
+}
+
+impl<A, P: VoxelPayload> Offset<A, P> {
+    pub fn new<S>(inner: A, offset: IVec3, tree_depth: u32, fill: P) -> Self where A: OctreeBuilder<S, P> {
+        assert!(tree_depth > 0 && tree_depth <= MAX_TREE_DEPTH, "The tree depth has to be between 1 and {}.", MAX_TREE_DEPTH);
+        let inner_depth = inner.get_tree_depth();
+        Self { inner, offset, tree_depth, inner_depth, fill }
+    }
+
+    // Position of the octant at pos inside of inner, None unless it lies fully inside of it
+    fn inner_position(&self, pos: UVec3, size: u32) -> Option<UVec3> {
+        let inner_min = pos.as_i64vec3() - self.offset.as_i64vec3();
+        let inner_max = inner_min + I64Vec3::splat(size as i64);
+        let inner_size = I64Vec3::splat(1 << self.inner_depth);
+        (inner_min.cmpge(I64Vec3::ZERO).all() && inner_max.cmple(inner_size).all()).then(|| inner_min.as_uvec3())
+    }
+
+    // Whether the octant at pos doesn't overlap inner at all
+    fn outside(&self, pos: UVec3, size: u32) -> bool {
+        let inner_min = pos.as_i64vec3() - self.offset.as_i64vec3();
+        let inner_max = inner_min + I64Vec3::splat(size as i64);
+        let inner_size = I64Vec3::splat(1 << self.inner_depth);
+        inner_max.cmple(I64Vec3::ZERO).any() || inner_min.cmpge(inner_size).any()
+    }
+
+    // Samples inner down to the octant at level containing pos, starting from the octant from
+    // containing it or from the root. Stops early at larger homogeneous octants.
+    fn descend<S: Clone>(&self, from: Option<&InnerOctant<S, P>>, pos: UVec3, level: u32, buffer: &mut UnmanagedByteBuffer) -> InnerOctant<S, P> where A: OctreeBuilder<S, P> {
+        let (mut octant, mut part) = match from {
+            Some(from) if from.level == level || from.part.material().is_some() => return from.clone(),
+            Some(from) => (Self::child_containing(&Self::inner_octant(from.min, from.level), pos), from.part.clone()),
+            None => (OctreeCreationPosition::new(UVec3::ZERO, self.inner_depth, VoxelOctant::Z0Y0X0), BuilderPart::Sampling(self.inner.default_state())),
+        };
+        loop {
+            part = part.step(&self.inner, &octant, buffer);
+            if octant.level() == level || part.material().is_some() {
+                return InnerOctant { min: octant.position(), level: octant.level(), part };
+            }
+            octant = Self::child_containing(&octant, pos);
+        }
+    }
+
+    fn inner_octant(pos: UVec3, level: u32) -> OctreeCreationPosition {
+        OctreeCreationPosition::new(pos, level, VoxelOctant::containing(pos, level))
+    }
+
+    // The child of octant containing pos
+    fn child_containing(octant: &OctreeCreationPosition, pos: UVec3) -> OctreeCreationPosition {
+        assert!(octant.level() > 0, "Octants at level 0 have no children.");
+        unsafe { octant.child_unchecked(VoxelOctant::containing(pos, octant.level() - 1)) }
+    }
+}
+
+impl<S: Clone, P: VoxelPayload, A: OctreeBuilder<S, P>> OctreeBuilder<OffsetState<S, P>, P> for Offset<A, P> {
+    fn default_state(&self) -> OffsetState<S, P> {
+        OffsetState::Unknown
+    }
+
+    fn get_tree_depth(&self) -> u32 {
+        self.tree_depth
+    }
+
+    fn get_octant(&self, pos: &OctreeCreationPosition, buffer: &mut UnmanagedByteBuffer, state: &OffsetState<S, P>) -> OctreeBuilderResult<OffsetState<S, P>, P> {
+        if self.outside(pos.position(), pos.size()) {
+            return OctreeBuilderResult::Homogeneous(self.fill);
+        }
+        let inner = match self.inner_position(pos.position(), pos.size()) {
+            Some(inner) => inner,
+            // Part of the octant is filled, part of it comes from inner
+            None => return OctreeBuilderResult::Sparse,
+        };
+
+        let (first, last) = (inner >> pos.level(), (inner + UVec3::splat(pos.size() - 1)) >> pos.level());
+        let mut overlapping: Vec<InnerOctant<S, P>> = Vec::with_capacity(8);
+        for x in first.x..=last.x {
+            for y in first.y..=last.y {
+                for z in first.z..=last.z {
+                    let min = UVec3::new(x, y, z) << pos.level();
+                    if overlapping.iter().any(|octant| octant.contains(min)) {
+                        continue;
+                    }
+                    let from = match state {
+                        OffsetState::Inside(parent) => parent.iter().find(|octant| octant.contains(min)),
+                        OffsetState::Unknown => None,
+                    };
+                    overlapping.push(self.descend(from, min, pos.level(), buffer));
+                }
+            }
+        }
+
+        match overlapping[0].part.material() {
+            Some(mat) if overlapping.iter().all(|octant| octant.part.material() == Some(mat)) => OctreeBuilderResult::Homogeneous(mat),
+            _ => OctreeBuilderResult::SamplingRequired(OffsetState::Inside(overlapping)),
+        }
+    }
+
+    fn get_block(&self, pos: &OctreeCreationPosition, buffer: &mut UnmanagedByteBuffer, state: &OffsetState<S, P>) -> [P; 8] {
+        let voxels: [Option<UVec3>; 8] = std::array::from_fn(|i| self.inner_position(pos.position() + VoxelOctant::from_index(i).to_offset(), 1));
+        let overlapping = match state {
+            OffsetState::Inside(overlapping) => overlapping.clone(),
+            // The block is only partly inside of inner, its blocks still have to be found
+            OffsetState::Unknown => {
+                let mut overlapping: Vec<InnerOctant<S, P>> = Vec::with_capacity(8);
+                for voxel in voxels.iter().flatten() {
+                    if !overlapping.iter().any(|octant| octant.contains(*voxel)) {
+                        overlapping.push(self.descend(None, *voxel, 1, buffer));
+                    }
+                }
+                overlapping
+            }
+        };
+
+        let blocks: Vec<[P; 8]> = overlapping.iter().map(|octant| octant.part.block(&self.inner, &Self::inner_octant(octant.min, 1), buffer)).collect();
+        std::array::from_fn(|i| match voxels[i] {
+            Some(voxel) => {
+                let octant = overlapping.iter().position(|octant| octant.contains(voxel)).expect("Every voxel of the block lies in an overlapping octant.");
+                blocks[octant][VoxelOctant::containing(voxel, 0).index()]
+            }
+            None => self.fill,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::octree::tests::random_array;
+    use crate::sdf::*;
+    use glam::DVec3;
+    use std::cell::Cell;
+
+    // Counts how often inner gets sampled
+    struct Counting<'a, B> {
+        inner: B,
+        calls: &'a Cell<usize>,
+    }
+
+    impl<'a, S, B: OctreeBuilder<S>> OctreeBuilder<S> for Counting<'a, B> {
+        fn default_state(&self) -> S {
+            self.inner.default_state()
+        }
+
+        fn get_tree_depth(&self) -> u32 {
+            self.inner.get_tree_depth()
+        }
+
+        fn get_octant(&self, pos: &OctreeCreationPosition, buffer: &mut UnmanagedByteBuffer, state: &S) -> OctreeBuilderResult<S> {
+            self.calls.set(self.calls.get() + 1);
+            self.inner.get_octant(pos, buffer, state)
+        }
+
+        fn get_block(&self, pos: &OctreeCreationPosition, buffer: &mut UnmanagedByteBuffer, state: &S) -> [u16; 8] {
+            self.calls.set(self.calls.get() + 1);
+            self.inner.get_block(pos, buffer, state)
+        }
+    }
+
+    // Builds voxels(pos) for every voxel of a 32x32x32 tree the plain way, so the buffer has to
+    // come out the same
+    fn expected_tree(voxels: impl Fn(UVec3) -> u16) -> VoxelOctree {
+        let mut array = multiarray::Array3D::new([32; 3], 0);
+        for x in 0..32 {
+            for y in 0..32 {
+                for z in 0..32 {
+                    array[[x, y, z]] = voxels(UVec3::new(x as u32, y as u32, z as u32));
+                }
+            }
+        }
+        VoxelOctree::from_builder(&Array3DOctreeBuilder::new(&array, 0))
+    }
+
+    fn at(array: &multiarray::Array3D<u16>, pos: UVec3) -> u16 {
+        array[[pos.x as usize, pos.y as usize, pos.z as usize]]
+    }
+
+    fn ball() -> SdfOctreeBuilder {
+        SdfOctreeBuilder::new(Sdf::sphere(DVec3::new(15.0, 17.0, 16.0), 11.0), 5, 5, 0)
+    }
+
+    // Reports the whole tree as a single homogeneous octant of 7
+    fn solid() -> SdfOctreeBuilder {
+        SdfOctreeBuilder::new(Sdf::cuboid(DVec3::splat(16.0), DVec3::splat(64.0)), 5, 7, 0)
+    }
+
+    #[test]
+    fn layered_matches_voxels() {
+        let (top, bottom) = (random_array([32, 32, 32], 4, 3, true), random_array([32, 32, 32], 5, 4, true));
+        let solid = solid();
+        let ball = ball();
+
+        let octree = VoxelOctree::from_builder(&Layered::new(Array3DOctreeBuilder::new(&top, 0), Array3DOctreeBuilder::new(&bottom, 0), 0));
+        let expected = expected_tree(|pos| if at(&top, pos) != 0 { at(&top, pos) } else { at(&bottom, pos) });
+        assert_eq!(octree.buffer().as_slice(), expected.buffer().as_slice());
+
+        // A homogeneous ball over noise and noise over a homogeneous block
+        let octree = VoxelOctree::from_builder(&Layered::new(&ball, Array3DOctreeBuilder::new(&bottom, 0), 0));
+        let expected = expected_tree(|pos| if ball.is_solid(pos) { 5 } else { at(&bottom, pos) });
+        assert_eq!(octree.buffer().as_slice(), expected.buffer().as_slice());
+
+        let octree = VoxelOctree::from_builder(&Layered::new(Array3DOctreeBuilder::new(&top, 0), &solid, 0));
+        let expected = expected_tree(|pos| if at(&top, pos) != 0 { at(&top, pos) } else { 7 });
+        assert_eq!(octree.buffer().as_slice(), expected.buffer().as_slice());
+    }
+
+    #[test]
+    fn masked_matches_voxels() {
+        let (inner, mask) = (random_array([32, 32, 32], 6, 4, true), random_array([32, 32, 32], 7, 2, true));
+        let solid = solid();
+        let ball = ball();
+
+        let octree = VoxelOctree::from_builder(&Masked::new(Array3DOctreeBuilder::new(&inner, 0), Array3DOctreeBuilder::new(&mask, 0), 0));
+        let expected = expected_tree(|pos| if at(&mask, pos) != 0 { at(&inner, pos) } else { 0 });
+        assert_eq!(octree.buffer().as_slice(), expected.buffer().as_slice());
+
+        let octree = VoxelOctree::from_builder(&Masked::new(Array3DOctreeBuilder::new(&inner, 0), &ball, 0));
+        let expected = expected_tree(|pos| if ball.is_solid(pos) { at(&inner, pos) } else { 0 });
+        assert_eq!(octree.buffer().as_slice(), expected.buffer().as_slice());
+
+        // The ball cuts through the single homogeneous octant of the source
+        let octree = VoxelOctree::from_builder(&Masked::new(&solid, &ball, 0));
+        let expected = expected_tree(|pos| if ball.is_solid(pos) { 7 } else { 0 });
+        assert_eq!(octree.buffer().as_slice(), expected.buffer().as_slice());
+    }
+
+    #[test]
+    fn material_remap_matches_voxels() {
+        let inner = random_array([32, 32, 32], 8, 4, true);
+        let ball = ball();
+        let remaps: [fn(u16) -> u16; 3] = [|m| (m * 3 + 1) % 5, |m| m.min(1), |_| 6];
+
+        for remap in remaps {
+            let octree = VoxelOctree::from_builder(&MaterialRemap::new(Array3DOctreeBuilder::new(&inner, 0), remap));
+            let expected = expected_tree(|pos| remap(at(&inner, pos)));
+            assert_eq!(octree.buffer().as_slice(), expected.buffer().as_slice());
+
+            let octree = VoxelOctree::from_builder(&MaterialRemap::new(&ball, remap));
+            let expected = expected_tree(|pos| remap(if ball.is_solid(pos) { 5 } else { 0 }));
+            assert_eq!(octree.buffer().as_slice(), expected.buffer().as_slice());
+        }
+    }
+
+    #[test]
+    fn clamp_matches_voxels() {
+        let inner = random_array([32, 32, 32], 9, 4, true);
+        let solid = solid();
+        let ball = ball();
+        let boxes = [
+            (UVec3::ZERO, UVec3::splat(32)),
+            (UVec3::new(8, 0, 16), UVec3::new(16, 32, 24)),
+            (UVec3::new(3, 5, 2), UVec3::new(29, 17, 30)),
+            (UVec3::new(5, 5, 5), UVec3::new(6, 6, 6)),
+            (UVec3::new(9, 9, 9), UVec3::new(9, 20, 20)),
+        ];
+
+        for (min, max) in boxes {
+            let inside = |pos: UVec3| pos.cmpge(min).all() && pos.cmplt(max).all();
+
+            let octree = VoxelOctree::from_builder(&Clamp::new(Array3DOctreeBuilder::new(&inner, 0), min, max, 9));
+            let expected = expected_tree(|pos| if inside(pos) { at(&inner, pos) } else { 9 });
+            assert_eq!(octree.buffer().as_slice(), expected.buffer().as_slice(), "box {} to {}", min, max);
+
+            let octree = VoxelOctree::from_builder(&Clamp::new(&ball, min, max, 9));
+            let expected = expected_tree(|pos| if !inside(pos) { 9 } else if ball.is_solid(pos) { 5 } else { 0 });
+            assert_eq!(octree.buffer().as_slice(), expected.buffer().as_slice(), "box {} to {}", min, max);
+
+            // The box cuts through the single homogeneous octant of the source
+            let octree = VoxelOctree::from_builder(&Clamp::new(&solid, min, max, 9));
+            let expected = expected_tree(|pos| if inside(pos) { 7 } else { 9 });
+            assert_eq!(octree.buffer().as_slice(), expected.buffer().as_slice(), "box {} to {}", min, max);
+        }
+    }
+
+    // What Offset should build, voxel by voxel
+    fn offset_array(inner: &multiarray::Array3D<u16>, offset: IVec3, tree_depth: u32, fill: u16) -> multiarray::Array3D<u16> {
+        let size = 1usize << tree_depth;
+        let extents = IVec3::new(inner.extents()[0] as i32, inner.extents()[1] as i32, inner.extents()[2] as i32);
+        let mut array = multiarray::Array3D::new([size; 3], fill);
+        for x in 0..size {
+            for y in 0..size {
+                for z in 0..size {
+                    let q = IVec3::new(x as i32, y as i32, z as i32) - offset;
+                    if q.cmpge(IVec3::ZERO).all() && q.cmplt(extents).all() {
+                        array[[x, y, z]] = inner[[q.x as usize, q.y as usize, q.z as usize]];
+                    }
+                }
+            }
+        }
+        array
+    }
+
+    #[test]
+    fn offset_matches_moved_array() {
+        let inner = random_array([32, 32, 32], 3, 4, true);
+        for offset in [IVec3::ZERO, IVec3::new(1, 0, 0), IVec3::new(3, 5, -7), IVec3::new(8, 0, 8), IVec3::new(-12, 20, 4), IVec3::new(31, 31, 31)] {
+            let octree = VoxelOctree::from_builder(&Offset::new(Array3DOctreeBuilder::new(&inner, 0), offset, 6, 9));
+            let expected = VoxelOctree::from_builder(&Array3DOctreeBuilder::new(&offset_array(&inner, offset, 6, 9), 0));
+            assert_eq!(octree.buffer().as_slice(), expected.buffer().as_slice(), "offset {:?}", offset);
+        }
+    }
+
+    #[test]
+    fn odd_offsets_pass_homogeneous_octants_through() {
+        let ball = SdfOctreeBuilder::new(Sdf::sphere(DVec3::new(15.0, 17.0, 16.0), 11.0), 5, 5, 0);
+        let calls = |offset: IVec3| {
+            let calls = Cell::new(0);
+            VoxelOctree::from_builder(&Offset::new(Counting { inner: &ball, calls: &calls }, offset, 6, 9));
+            calls.get()
+        };
+        // Shifted by a voxel every octant overlaps eight octants of inner and every octant of
+        // inner is asked about by eight octants, but the homogeneous inside and outside of the
+        // ball must not be sampled voxel by voxel
+        let aligned = calls(IVec3::new(16, 16, 16));
+        let odd = calls(IVec3::new(17, 15, 17));
+        assert!(odd < aligned * 32, "{} calls at an odd offset, {} aligned", odd, aligned);
+    }
+}
\ No newline at end of file
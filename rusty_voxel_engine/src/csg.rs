@@ -0,0 +1,231 @@
+// csg.rs
+use crate::octree::*;
+use crate::types::*;
+
+// The operations take the material of empty space, every other material is solid
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CsgOp<T> {
+    Union,
+    Subtract,
+    Intersect,
+    Replace { from: T, to: T },
+}
+
+// The result of an operation over a whole octant, known from a homogeneous side alone
+enum CsgShortcut<T> {
+    Homogeneous(VoxelRecord<T>),
+    Left,
+}
+
+impl<T: VoxelPayload> CsgOp<T> {
+    // Voxels emptied by an operation get the default layer values of empty
+    fn apply(self, a: VoxelRecord<T>, b: VoxelRecord<T>, empty: VoxelRecord<T>) -> VoxelRecord<T> {
+        match self {
+            CsgOp::Union => if b.material != empty.material { b } else { a },
+            CsgOp::Subtract => if b.material != empty.material { empty } else { a },
+            CsgOp::Intersect => if b.material != empty.material { a } else { empty },
+            CsgOp::Replace { from, to } => if b.material != empty.material && a.material == from { VoxelRecord { material: to, ..a } } else { a },
+        }
+    }
+
+    fn shortcut(self, a: OctreeSlot<T>, b: OctreeSlot<T>, empty: VoxelRecord<T>) -> Option<CsgShortcut<T>> {
+        let is_empty = |slot: OctreeSlot<T>| matches!(slot, OctreeSlot::Homogeneous(record) if record.material == empty.material);
+        match self {
+            CsgOp::Union if is_empty(b) => Some(CsgShortcut::Left),
+            CsgOp::Union => match b {
+                OctreeSlot::Homogeneous(record) => Some(CsgShortcut::Homogeneous(record)),
+                _ => None,
+            },
+            CsgOp::Subtract if is_empty(b) => Some(CsgShortcut::Left),
+            CsgOp::Subtract if a == OctreeSlot::Homogeneous(empty) => Some(CsgShortcut::Left),
+            CsgOp::Subtract => match b {
+                OctreeSlot::Homogeneous(_) => Some(CsgShortcut::Homogeneous(empty)),
+                _ => None,
+            },
+            CsgOp::Intersect if is_empty(b) || a == OctreeSlot::Homogeneous(empty) => Some(CsgShortcut::Homogeneous(empty)),
+            CsgOp::Intersect => match b {
+                OctreeSlot::Homogeneous(_) => Some(CsgShortcut::Left),
+                _ => None,
+            },
+            CsgOp::Replace { from, .. } => match a {
+                _ if is_empty(b) => Some(CsgShortcut::Left),
+                OctreeSlot::Homogeneous(record) if record.material != from => Some(CsgShortcut::Left),
+                _ => None,
+            },
+        }
+    }
+}
+
+impl<T: VoxelPayload> VoxelOctree<T> {
+    // Adds the solid voxels of other to the tree, they win over the ones already there.
+    // Voxels of the material empty count as empty space in these operations.
+    pub fn union(&self, other: &VoxelOctree<T>, empty: T) -> VoxelOctree<T> {
+        self.combine(other, CsgOp::Union, empty)
+    }
+
+    // Carves the solid voxels of other out of the tree
+    pub fn subtract(&self, other: &VoxelOctree<T>, empty: T) -> VoxelOctree<T> {
+        self.combine(other, CsgOp::Subtract, empty)
+    }
+
+    // Keeps only the voxels of the tree where other is solid
+    pub fn intersect(&self, other: &VoxelOctree<T>, empty: T) -> VoxelOctree<T> {
+        self.combine(other, CsgOp::Intersect, empty)
+    }
+
+    // Turns the voxels of material from into to where other is solid, their layers are kept
+    pub fn replace_material(&self, other: &VoxelOctree<T>, from: T, to: T, empty: T) -> VoxelOctree<T> {
+        self.combine(other, CsgOp::Replace { from, to }, empty)
+    }
+
+    fn combine(&self, other: &VoxelOctree<T>, op: CsgOp<T>, empty: T) -> VoxelOctree<T> {
+        assert!(self.tree_depth == other.tree_depth, "Only octrees of the same depth can be combined.");
+        assert!(self.layers() == other.layers(), "Only octrees with the same layers can be combined.");
+
+        let mut buffer = self.new_stream(0);
+
+        let empty = self.default_record(empty);
+        let top = self.combine_slot(other, self.top_slot(), other.top_slot(), op, empty, &mut buffer);
+        self.write_top(&mut buffer, top);
+
+        self.with_layout(self.tree_depth, buffer)
+    }
+
+    // Combines two octants at the same position, homogeneous octants only get split when
+    // the result depends on the other side
+    fn combine_slot(&self, other: &VoxelOctree<T>, a: OctreeSlot<T>, b: OctreeSlot<T>, op: CsgOp<T>, empty: VoxelRecord<T>, buffer: &mut UnmanagedByteBuffer) -> OctreeSlot<T> {
+        if let (OctreeSlot::Homogeneous(rec_a), OctreeSlot::Homogeneous(rec_b)) = (a, b) {
+            return OctreeSlot::Homogeneous(op.apply(rec_a, rec_b, empty));
+        }
+
+        match op.shortcut(a, b, empty) {
+            Some(CsgShortcut::Homogeneous(record)) => return OctreeSlot::Homogeneous(record),
+            Some(CsgShortcut::Left) => return self.copy_slot(a, buffer),
+            None => {}
+        }
+
+        let children_a = match a {
+            OctreeSlot::Homogeneous(_) => [a; 8],
+            OctreeSlot::Node(node) => self.children(node),
+        };
+        let children_b = match b {
+            OctreeSlot::Homogeneous(_) => [b; 8],
+            OctreeSlot::Node(node) => other.children(node),
+        };
+
+        let mut children = [OctreeSlot::Node(0); 8];
+        for (i, child) in children.iter_mut().enumerate() {
+            *child = self.combine_slot(other, children_a[i], children_b[i], op, empty, buffer);
+        }
+
+        match Self::merged_material(&children) {
+            Some(record) => OctreeSlot::Homogeneous(record),
+            None => OctreeSlot::Node(self.write_node(buffer, &children)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::*;
+    use crate::octree::tests::random_array;
+    use glam::UVec3;
+
+    const EMPTY: u16 = 0;
+
+    fn build(f: impl Fn(UVec3) -> u16) -> VoxelOctree {
+        let mut array = multiarray::Array3D::new([16, 16, 16], 0u16);
+        for x in 0..16 {
+            for y in 0..16 {
+                for z in 0..16 {
+                    array[[x, y, z]] = f(UVec3::new(x as u32, y as u32, z as u32));
+                }
+            }
+        }
+        VoxelOctree::from_builder(&Array3DOctreeBuilder::new(&array, 0))
+    }
+
+    fn assert_combines(result: &VoxelOctree, a: &VoxelOctree, b: &VoxelOctree, op: CsgOp<u16>) {
+        let empty = VoxelRecord { material: EMPTY, layers: [0; MAX_LAYERS] };
+        assert_eq!(result.verify_packed(), Ok(()));
+        for x in 0..16 {
+            for y in 0..16 {
+                for z in 0..16 {
+                    let pos = UVec3::new(x, y, z);
+                    let expected = op.apply(a.get_record(pos), b.get_record(pos), empty).material;
+                    assert_eq!(result.get(pos), expected, "{:?} at {}", op, pos);
+                }
+            }
+        }
+    }
+
+    fn is_filled_with(octree: &VoxelOctree, material: u16) -> bool {
+        octree.stream_len() == octree.top_len() && octree.get(UVec3::ZERO) == material
+    }
+
+    #[test]
+    fn combines_every_kind_of_octant() {
+        let noise = random_array([16, 16, 16], 3, 4, true);
+        let detailed = build(|p| noise[[p.x as usize, p.y as usize, p.z as usize]]);
+        let halves = build(|p| if p.x < 8 { 3 } else { EMPTY });
+        let ball = build(|p| if p.as_vec3().distance(glam::Vec3::splat(7.5)) < 6.0 { 2 } else { EMPTY });
+        let trees = [detailed, halves, ball, VoxelOctree::filled(4, EMPTY), VoxelOctree::filled(4, 2), VoxelOctree::filled(4, 5)];
+
+        for a in &trees {
+            for b in &trees {
+                assert_combines(&a.union(b, EMPTY), a, b, CsgOp::Union);
+                assert_combines(&a.subtract(b, EMPTY), a, b, CsgOp::Subtract);
+                assert_combines(&a.intersect(b, EMPTY), a, b, CsgOp::Intersect);
+                assert_combines(&a.replace_material(b, 2, 7, EMPTY), a, b, CsgOp::Replace { from: 2, to: 7 });
+            }
+        }
+    }
+
+    #[test]
+    fn short_circuits_homogeneous_sides() {
+        let noise = random_array([16, 16, 16], 5, 4, false);
+        let a = build(|p| noise[[p.x as usize, p.y as usize, p.z as usize]]);
+        let (empty, solid) = (VoxelOctree::filled(4, EMPTY), VoxelOctree::filled(4, 6));
+        let same = |octree: VoxelOctree| octree.buffer().as_slice() == a.buffer().as_slice();
+
+        // An empty other side leaves the tree as it is, except for intersect which empties it
+        assert!(same(a.union(&empty, EMPTY)));
+        assert!(same(a.subtract(&empty, EMPTY)));
+        assert!(same(a.replace_material(&empty, 1, 9, EMPTY)));
+        assert!(is_filled_with(&a.intersect(&empty, EMPTY), EMPTY));
+
+        // A solid other side decides the whole tree, except for intersect which keeps it
+        assert!(is_filled_with(&a.union(&solid, EMPTY), 6));
+        assert!(is_filled_with(&a.subtract(&solid, EMPTY), EMPTY));
+        assert!(same(a.intersect(&solid, EMPTY)));
+
+        // An empty tree stays empty when subtracted from or intersected
+        assert!(is_filled_with(&empty.subtract(&a, EMPTY), EMPTY));
+        assert!(is_filled_with(&empty.intersect(&a, EMPTY), EMPTY));
+        // Homogeneous octants of other materials are left alone by replace
+        let stone = VoxelOctree::filled(4, 5);
+        assert!(is_filled_with(&stone.replace_material(&a, 1, 9, EMPTY), 5));
+    }
+
+    #[test]
+    fn combines_other_payloads() {
+        let mut a = VoxelOctree::filled(3, 0u64);
+        let mut b = VoxelOctree::filled(3, u64::MAX);
+        a.set(UVec3::new(1, 2, 3), 1 << 40);
+        b.set(UVec3::new(1, 2, 3), u64::MAX);
+        b.set(UVec3::new(4, 4, 4), u64::MAX - 1);
+        b.set(UVec3::new(5, 5, 5), u64::MAX - 1);
+        b.fill(UVec3::ZERO, UVec3::new(3, 3, 0), u64::MAX - 1);
+
+        // u64::MAX is empty space here
+        let union = a.union(&b, u64::MAX);
+        assert_eq!(union.get(UVec3::new(1, 2, 3)), 1 << 40);
+        assert_eq!(union.get(UVec3::new(4, 4, 4)), u64::MAX - 1);
+        assert_eq!(union.get(UVec3::new(7, 7, 7)), 0);
+        let intersect = a.intersect(&b, u64::MAX);
+        assert_eq!(intersect.get(UVec3::new(5, 5, 5)), 0);
+        assert_eq!(intersect.get(UVec3::new(1, 2, 3)), u64::MAX);
+        assert_eq!(intersect.verify_packed(), Ok(()));
+    }
+}
\ No newline at end of file
@@ -0,0 +1,219 @@
+// dag.rs
+use crate::octree::*;
+use crate::types::*;
+
+use std::collections::HashMap;
+
+// Size of an octree as a plain tree and as a DAG, in nodes and buffer words
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OctreeDagStats {
+    pub tree_nodes: usize,
+    pub tree_words: usize,
+    pub dag_nodes: usize,
+    pub dag_words: usize,
+}
+
+impl OctreeDagStats {
+    // How many times smaller the DAG is than the plain tree
+    pub fn ratio(&self) -> f64 {
+        self.tree_words as f64 / self.dag_words as f64
+    }
+}
+
+impl std::fmt::Display for OctreeDagStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} nodes / {} words as a tree, {} nodes / {} words as a DAG ({:.2}x)", self.tree_nodes, self.tree_words, self.dag_nodes, self.dag_words, self.ratio())
+    }
+}
+
+// Rewrites a tree bottom up, storing every distinct node once
+struct DagWriter<'a, T: VoxelPayload> {
+    octree: &'a VoxelOctree<T>,
+    buffer: UnmanagedByteBuffer,
+    // Encoded node -> its position in the new buffer
+    nodes: HashMap<Vec<u16>, u32>,
+    // Source node -> (new slot, node count and word count of its plain subtree)
+    visited: HashMap<u32, (OctreeSlot<T>, usize, usize)>,
+}
+
+impl<'a, T: VoxelPayload> DagWriter<'a, T> {
+    fn write_slot(&mut self, slot: OctreeSlot<T>) -> (OctreeSlot<T>, usize, usize) {
+        let node = match slot {
+            OctreeSlot::Homogeneous(_) => return (slot, 0, 0),
+            OctreeSlot::Node(node) => node,
+        };
+
+        // The source may already be a DAG, every shared node only has to be written once
+        if let Some(res) = self.visited.get(&node) {
+            return *res;
+        }
+
+        let mut children = self.octree.children(node);
+        let mut tree_nodes = 1;
+        let mut tree_words = self.octree.node_len(node);
+        for child in children.iter_mut() {
+            let (slot, nodes, words) = self.write_slot(*child);
+            *child = slot;
+            tree_nodes += nodes;
+            tree_words += words;
+        }
+
+        let (words, len) = self.octree.node_words(&children);
+        let buffer = &mut self.buffer;
+        let ptr = *self.nodes.entry(words[..len].to_vec()).or_insert_with(|| {
+            let ptr = buffer.count() as u32;
+            buffer.extend_from_slice(&words[..len]);
+            ptr
+        });
+
+        let res = (OctreeSlot::Node(ptr), tree_nodes, tree_words);
+        self.visited.insert(node, res);
+        res
+    }
+}
+
+impl<T: VoxelPayload> VoxelOctree<T> {
+    // Rewrites the tree as a sparse voxel DAG, identical subtrees are stored once.
+    // Reads work as before, edits turn the DAG back into a plain tree first.
+    pub fn compact_dag(&mut self) -> OctreeDagStats {
+        let mut writer = DagWriter { octree: self, buffer: self.new_stream(0), nodes: HashMap::new(), visited: HashMap::new() };
+
+        let (top, tree_nodes, tree_words) = writer.write_slot(self.top_slot());
+        let dag_nodes = writer.nodes.len();
+        let mut buffer = writer.buffer;
+
+        self.write_top(&mut buffer, top);
+        if let OctreeSlot::Node(_) = top {
+            buffer.set(0, Self::node_header(NodeFlags::DAG, 0));
+        }
+
+        self.replace_buffer(buffer);
+
+        OctreeDagStats { tree_nodes, tree_words: tree_words + self.top_len(), dag_nodes, dag_words: self.buffer.count() }
+    }
+
+    pub fn is_dag(&self) -> bool {
+        NodeFlags::from_bits_retain((self.word(self.top) >> 8) as u8).contains(NodeFlags::DAG)
+    }
+
+    // Turns a DAG back into a plain tree that can be edited in place
+    pub fn expand_dag(&mut self) {
+        self.compact();
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::*;
+    use crate::octree::tests::random_array;
+    use glam::UVec3;
+
+    // 16^3 array made of eight copies of an 8^3 block where every voxel has its own material
+    fn repeated_blocks() -> multiarray::Array3D<u16> {
+        let mut array = multiarray::Array3D::new([16, 16, 16], 0u16);
+        for x in 0..16 {
+            for y in 0..16 {
+                for z in 0..16 {
+                    array[[x, y, z]] = ((x % 8) * 64 + (y % 8) * 8 + z % 8) as u16 + 1;
+                }
+            }
+        }
+        array
+    }
+
+    fn arrays() -> Vec<multiarray::Array3D<u16>> {
+        vec![repeated_blocks(), random_array([32, 32, 32], 3, 2, true), random_array([16, 16, 16], 8, 2, false), multiarray::Array3D::new([8, 8, 8], 6)]
+    }
+
+    #[test]
+    fn reads_match_the_source_tree() {
+        for array in arrays() {
+            let source = VoxelOctree::from_builder(&Array3DOctreeBuilder::new(&array, 0));
+            let mut dag = VoxelOctree::from_builder(&Array3DOctreeBuilder::new(&array, 0));
+            dag.compact_dag();
+
+            let size = source.size();
+            for x in 0..size {
+                for y in 0..size {
+                    for z in 0..size {
+                        let pos = UVec3::new(x, y, z);
+                        assert_eq!(dag.get(pos), source.get(pos), "at {}", pos);
+                    }
+                }
+            }
+            for (min, max) in [(UVec3::ZERO, UVec3::splat(size)), (UVec3::new(1, 2, 3), UVec3::new(7, 8, 5)), (UVec3::splat(size / 2 - 1), UVec3::splat(size / 2 + 3))] {
+                assert_eq!(dag.get_region(min, max).as_slice(), source.get_region(min, max).as_slice());
+            }
+        }
+    }
+
+    #[test]
+    fn verifies_and_is_flagged() {
+        for array in arrays() {
+            let mut octree = VoxelOctree::from_builder(&Array3DOctreeBuilder::new(&array, 0));
+            assert!(!octree.is_dag());
+            octree.compact_dag();
+            assert_eq!(octree.verify_packed(), Ok(()));
+            // A single material has no nodes to share, the top slot stays homogeneous
+            assert_eq!(octree.is_dag(), matches!(octree.top_slot(), OctreeSlot::Node(_)));
+        }
+    }
+
+    #[test]
+    fn stats_count_repeated_subtrees_once() {
+        let mut octree = VoxelOctree::from_builder(&Array3DOctreeBuilder::new(&repeated_blocks(), 0));
+        let tree_words = octree.stream_len();
+        let stats = octree.compact_dag();
+
+        // An 8^3 block without merges has 1 + 8 + 64 nodes, the 64 at level 1 hold eight
+        // voxels and the others eight pointers
+        let (block_nodes, block_words) = (73, 9 * 17 + 64 * 9);
+        assert_eq!(stats, OctreeDagStats {
+            tree_nodes: 1 + 8 * block_nodes,
+            tree_words: 3 + 17 + 8 * block_words,
+            dag_nodes: 1 + block_nodes,
+            dag_words: 3 + 17 + block_words,
+        });
+        assert_eq!(stats.tree_words, tree_words);
+        assert_eq!(stats.dag_words, octree.stream_len());
+        assert!((stats.ratio() - stats.tree_words as f64 / stats.dag_words as f64).abs() < 1e-12);
+        assert!(stats.ratio() > 7.0);
+    }
+
+    #[test]
+    fn edits_copy_shared_nodes() {
+        let mut expected = repeated_blocks();
+        let mut octree = VoxelOctree::from_builder(&Array3DOctreeBuilder::new(&expected, 0));
+        octree.compact_dag();
+
+        // Every block shares its nodes with the other seven, only the edited one may change
+        for (pos, material) in [(UVec3::new(1, 2, 3), 1000), (UVec3::new(9, 2, 3), 1001), (UVec3::new(15, 15, 15), 0)] {
+            octree.set(pos, material);
+            expected[[pos.x as usize, pos.y as usize, pos.z as usize]] = material;
+            assert!(!octree.is_dag());
+            assert_eq!(octree.verify(), Ok(()));
+        }
+        crate::octree::tests::assert_matches_array(&octree, &expected, 0);
+
+        octree.compact_dag();
+        octree.set_many([(UVec3::new(4, 4, 4), 7), (UVec3::new(12, 4, 4), 8)]);
+        expected[[4, 4, 4]] = 7;
+        expected[[12, 4, 4]] = 8;
+        assert_eq!(octree.verify(), Ok(()));
+        crate::octree::tests::assert_matches_array(&octree, &expected, 0);
+    }
+
+    #[test]
+    fn expanding_gives_the_compacted_tree() {
+        for array in arrays() {
+            let mut plain = VoxelOctree::from_builder(&Array3DOctreeBuilder::new(&array, 0));
+            plain.compact();
+            let mut octree = VoxelOctree::from_builder(&Array3DOctreeBuilder::new(&array, 0));
+            octree.compact_dag();
+            octree.expand_dag();
+            assert!(!octree.is_dag());
+            assert_eq!(octree.buffer().as_slice(), plain.buffer().as_slice());
+            assert_eq!(octree.verify_packed(), Ok(()));
+        }
+    }
+}
\ No newline at end of file
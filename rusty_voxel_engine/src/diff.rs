@@ -0,0 +1,167 @@
+// diff.rs
+use crate::octree::*;
+use crate::types::*;
+
+use glam::UVec3;
+use std::sync::Arc;
+
+// A single step turning one octree into another, edits carry the material and the layers
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VoxelEdit<T = u16> {
+    // Sets the voxel at pos
+    Set { pos: UVec3, record: VoxelRecord<T> },
+    // Fills the box between min and max, both inclusive
+    Fill { min: UVec3, max: UVec3, record: VoxelRecord<T> },
+}
+
+impl<T> VoxelEdit<T> {
+    // The edit filling the octant of edge length 1 << level at min
+    fn octant(min: UVec3, level: u32, record: VoxelRecord<T>) -> Self {
+        if level == 0 {
+            VoxelEdit::Set { pos: min, record }
+        } else {
+            VoxelEdit::Fill { min, max: min + UVec3::splat((1 << level) - 1), record }
+        }
+    }
+}
+
+// Returns the edits turning a into b. Both trees are walked at once, subtrees that are
+// homogeneous on both sides or shared through a snapshot are skipped without descending.
+// Any other subtree is walked down to its homogeneous octants even when it is identical on
+// both sides, as pointers of two trees say nothing about each other. Diffing trees that
+// don't share a snapshot costs a walk over all nodes of b, no matter how few edits come out.
+pub fn diff<T: VoxelPayload>(a: &VoxelOctree<T>, b: &VoxelOctree<T>) -> Vec<VoxelEdit<T>> {
+    assert!(a.tree_depth() == b.tree_depth(), "Only octrees of the same depth can be compared.");
+    assert!(a.layers() == b.layers(), "Only octrees with the same layers can be compared.");
+
+    let mut differ = Differ { a, b, shared: shared_len(a, b), edits: Vec::new() };
+    differ.diff_slot(a.top_slot(), b.top_slot(), UVec3::ZERO, a.tree_depth());
+    differ.edits
+}
+
+// Length of the node stream prefix both trees share through snapshots. Nodes in it are
+// identical on both sides, and so are their children since those are written first.
+fn shared_len<T: VoxelPayload>(a: &VoxelOctree<T>, b: &VoxelOctree<T>) -> usize {
+    a.frozen.iter().zip(b.frozen.iter())
+        .take_while(|((start_a, words_a), (start_b, words_b))| start_a == start_b && Arc::ptr_eq(words_a, words_b))
+        .last()
+        .map_or(0, |((start, words), _)| start + words.count())
+}
+
+struct Differ<'a, T: VoxelPayload> {
+    a: &'a VoxelOctree<T>,
+    b: &'a VoxelOctree<T>,
+    shared: usize,
+    edits: Vec<VoxelEdit<T>>,
+}
+
+impl<'a, T: VoxelPayload> Differ<'a, T> {
+    fn diff_slot(&mut self, slot_a: OctreeSlot<T>, slot_b: OctreeSlot<T>, min: UVec3, level: u32) {
+        match (slot_a, slot_b) {
+            (OctreeSlot::Homogeneous(rec_a), OctreeSlot::Homogeneous(rec_b)) if rec_a == rec_b => {}
+            (OctreeSlot::Node(node_a), OctreeSlot::Node(node_b)) if node_a == node_b && (node_a as usize) < self.shared => {}
+            (_, OctreeSlot::Homogeneous(record)) => self.edits.push(VoxelEdit::octant(min, level, record)),
+            (_, OctreeSlot::Node(node_b)) => {
+                let children_a = match slot_a {
+                    OctreeSlot::Homogeneous(_) => [slot_a; 8],
+                    OctreeSlot::Node(node_a) => self.a.children(node_a),
+                };
+                let children_b = self.b.children(node_b);
+
+                for i in 0..8 {
+                    let child_min = min + (VoxelOctant::from_index(i).to_offset() << (level - 1));
+                    self.diff_slot(children_a[i], children_b[i], child_min, level - 1);
+                }
+            }
+        }
+    }
+}
+
+impl<T: VoxelPayload> VoxelOctree<T> {
+    // Replays edits produced by diff, the buffer gets compacted at most once at the end
+    pub fn apply(&mut self, edits: &[VoxelEdit<T>]) {
+        for edit in edits {
+            match *edit {
+                VoxelEdit::Set { pos, record } => self.edit_voxel(pos, |_| record),
+                VoxelEdit::Fill { min, max, record } => self.edit_box(min, max, |_| record),
+            }
+        }
+        self.compact_if_fragmented();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::*;
+    use crate::octree::tests::random_array;
+
+    fn random_edits(seed: u64, count: usize, size: u32) -> Vec<(UVec3, u16)> {
+        let mut state = seed;
+        let mut next = move || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state
+        };
+        (0..count).map(|_| (UVec3::new(next() as u32 % size, next() as u32 % size, next() as u32 % size), (next() % 4) as u16)).collect()
+    }
+
+    // Compacted trees are laid out the same way whenever they hold the same voxels
+    fn assert_same_tree(a: &VoxelOctree, b: &VoxelOctree) {
+        let (mut a, mut b) = (a.clone(), b.clone());
+        a.compact();
+        b.compact();
+        assert_eq!(a.buffer().as_slice(), b.buffer().as_slice());
+    }
+
+    #[test]
+    fn apply_reproduces_edited_trees() {
+        for seed in 1..6 {
+            let array = random_array([16, 16, 16], seed, 3, seed % 2 == 0);
+            let a = VoxelOctree::from_builder(&Array3DOctreeBuilder::new(&array, 0));
+            let mut b = a.clone();
+            b.set_many(random_edits(seed, 200, 16));
+
+            let edits = diff(&a, &b);
+            let mut applied = a.clone();
+            applied.apply(&edits);
+            assert_eq!(applied.verify(), Ok(()));
+            assert_same_tree(&applied, &b);
+            assert!(diff(&applied, &b).is_empty());
+        }
+
+        // Unrelated trees too
+        let a = VoxelOctree::from_builder(&Array3DOctreeBuilder::new(&random_array([16, 16, 16], 4, 3, true), 0));
+        let b = VoxelOctree::from_builder(&Array3DOctreeBuilder::new(&random_array([16, 16, 16], 9, 5, false), 0));
+        let mut applied = a.clone();
+        applied.apply(&diff(&a, &b));
+        assert_same_tree(&applied, &b);
+    }
+
+    #[test]
+    fn apply_reproduces_trees_sharing_a_snapshot() {
+        for seed in 1..6 {
+            let array = random_array([32, 32, 32], seed, 3, true);
+            let mut a = VoxelOctree::from_builder(&Array3DOctreeBuilder::new(&array, 0));
+            let snapshot = a.snapshot();
+            let mut b = snapshot.to_octree();
+            let changes = random_edits(seed + 10, 20, 32);
+            b.set_many(changes.iter().copied());
+
+            // Only the paths to the edited voxels differ, everything else is skipped
+            let edits = diff(&a, &b);
+            assert!(edits.len() <= changes.len(), "{} edits for {} changes", edits.len(), changes.len());
+
+            let mut applied = a.clone();
+            applied.apply(&edits);
+            assert_eq!(applied.verify(), Ok(()));
+            assert_same_tree(&applied, &b);
+
+            // Both directions work, and a is still the snapshot
+            let mut reverted = b.clone();
+            reverted.apply(&diff(&b, &a));
+            assert_same_tree(&reverted, &snapshot.to_octree());
+        }
+    }
+}
\ No newline at end of file
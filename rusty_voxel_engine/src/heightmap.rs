@@ -0,0 +1,201 @@
+// heightmap.rs
+use crate::builder::*;
+use crate::types::*;
+
+use glam::{UVec2, UVec3};
+use noise::{Fbm, MultiFractal, NoiseFn, Perlin, RidgedMulti};
+use rayon::prelude::*;
+
+// Every column is sampled up front and takes 8 bytes plus a third more for the pyramid, at
+// this depth that is about 180 MB
+pub const MAX_HEIGHTMAP_DEPTH: u32 = 12;
+
+// Shape of the terrain and the materials it is made of. y is up, every column of the tree
+// gets a surface height, the voxel at that height is grass with dirt_depth voxels of dirt
+// below it and stone further down.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HeightmapSettings {
+    pub seed: u32,
+    // Height of the terrain where the noise is 0
+    pub base_height: f64,
+    // Largest distance of the surface from base_height
+    pub amplitude: f64,
+    // Frequency of the first octave in cycles per voxel
+    pub frequency: f64,
+    pub octaves: usize,
+    // Share of ridged noise in the mix, 0 for rolling hills and 1 for sharp ridges
+    pub ridge_weight: f64,
+    pub dirt_depth: u32,
+    pub air: u16,
+    pub grass: u16,
+    pub dirt: u16,
+    pub stone: u16,
+}
+
+impl Default for HeightmapSettings {
+    fn default() -> Self {
+        Self {
+            seed: 0,
+            base_height: 64.0,
+            amplitude: 32.0,
+            frequency: 1.0 / 256.0,
+            octaves: 6,
+            ridge_weight: 0.3,
+            dirt_depth: 3,
+            air: 0,
+            grass: 1,
+            dirt: 2,
+            stone: 3,
+        }
+    }
+}
+
+// Builds terrain from a heightfield of fBm and ridged noise. The heights are sampled once per
+// column up front and kept in a min/max pyramid, so every octant knows the range of the
+// surface above its footprint and octants fully in the air or in the stone are homogeneous.
+pub struct HeightmapNoiseBuilder {
+    settings: HeightmapSettings,
+    tree_depth: u32,
+    // Level l holds the lowest and highest surface of every 2^l x 2^l block of columns
+    bounds: Vec<Vec<(i32, i32)>>,
+}
+
+impl HeightmapNoiseBuilder {
+    pub fn new(tree_depth: u32, settings: HeightmapSettings) -> Self {
+        assert!(tree_depth > 0 && tree_depth <= MAX_HEIGHTMAP_DEPTH, "The tree depth of a heightmap has to be between 1 and {}.", MAX_HEIGHTMAP_DEPTH);
+
+        let fbm = Fbm::<Perlin>::new(settings.seed).set_octaves(settings.octaves).set_frequency(settings.frequency);
+        let ridged = RidgedMulti::<Perlin>::new(settings.seed.wrapping_add(1)).set_octaves(settings.octaves).set_frequency(settings.frequency);
+
+        let size = 1usize << tree_depth;
+        let heights: Vec<(i32, i32)> = (0..size * size).into_par_iter()
+            .map(|i| {
+                let point = [(i % size) as f64, (i / size) as f64];
+                let noise = fbm.get(point) * (1.0 - settings.ridge_weight) + ridged.get(point) * settings.ridge_weight;
+                let height = (settings.base_height + noise * settings.amplitude).round() as i32;
+                (height, height)
+            })
+            .collect();
+
+        let mut bounds = vec![heights];
+        for level in 1..=tree_depth {
+            let below = &bounds[level as usize - 1];
+            let (size, below_size) = (size >> level, size >> (level - 1));
+            let merged = (0..size * size)
+                .map(|i| {
+                    let (x, z) = (i % size * 2, i / size * 2);
+                    [(x, z), (x + 1, z), (x, z + 1), (x + 1, z + 1)].iter()
+                        .map(|(x, z)| below[z * below_size + x])
+                        .fold((i32::MAX, i32::MIN), |(lo, hi), (l, h)| (lo.min(l), hi.max(h)))
+                })
+                .collect();
+            bounds.push(merged);
+        }
+
+        Self { settings, tree_depth, bounds }
+    }
+
+    pub fn settings(&self) -> &HeightmapSettings {
+        &self.settings
+    }
+
+    // Height of the surface in the column at x, z
+    pub fn height(&self, column: UVec2) -> i32 {
+        self.column_bounds(column, 0).0
+    }
+
+    // Lowest and highest surface over the 2^level x 2^level block of columns containing column
+    fn column_bounds(&self, column: UVec2, level: u32) -> (i32, i32) {
+        let size = 1usize << (self.tree_depth - level);
+        self.bounds[level as usize][(column.y >> level) as usize * size + (column.x >> level) as usize]
+    }
+
+    fn material(&self, pos: UVec3) -> u16 {
+        let depth = self.height(UVec2::new(pos.x, pos.z)) as i64 - pos.y as i64;
+        match depth {
+            d if d < 0 => self.settings.air,
+            0 => self.settings.grass,
+            d if d <= self.settings.dirt_depth as i64 => self.settings.dirt,
+            _ => self.settings.stone,
+        }
+    }
+}
+
+impl OctreeBuilder<()> for HeightmapNoiseBuilder {
+    fn default_state(&self) {}
+
+    fn get_tree_depth(&self) -> u32 {
+        self.tree_depth
+    }
+
+    fn get_octant(&self, pos: &OctreeCreationPosition, _buffer: &mut UnmanagedByteBuffer, _state: &()) -> OctreeBuilderResult<()> {
+        if pos.level() == 0 {
+            return OctreeBuilderResult::Homogeneous(self.material(pos.position()));
+        }
+
+        let p = pos.position();
+        let (lowest, highest) = self.column_bounds(UVec2::new(p.x, p.z), pos.level());
+        let (bottom, top) = (p.y as i64, p.y as i64 + pos.size() as i64 - 1);
+
+        if bottom > highest as i64 {
+            OctreeBuilderResult::Homogeneous(self.settings.air)
+        } else if top < lowest as i64 - self.settings.dirt_depth as i64 {
+            OctreeBuilderResult::Homogeneous(self.settings.stone)
+        } else {
+            OctreeBuilderResult::Sparse
+        }
+    }
+
+    fn get_block(&self, pos: &OctreeCreationPosition, _buffer: &mut UnmanagedByteBuffer, _state: &()) -> [u16; 8] {
+        VoxelOctant::ALL.map(|octant| self.material(pos.position() + octant.to_offset()))
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pruned_build_matches_columns() {
+        for seed in 0..4 {
+            let settings = HeightmapSettings { seed, base_height: 32.0, amplitude: 20.0, frequency: 1.0 / 32.0, ..Default::default() };
+            let builder = HeightmapNoiseBuilder::new(6, settings.clone());
+            let octree = VoxelOctree::from_builder(&builder);
+            let mut counts = [0; 4];
+
+            for x in 0..64 {
+                for z in 0..64 {
+                    let height = builder.height(UVec2::new(x, z)) as i64;
+                    for y in 0..64 {
+                        let depth = height - y as i64;
+                        let expected = if depth < 0 {
+                            settings.air
+                        } else if depth == 0 {
+                            settings.grass
+                        } else if depth <= settings.dirt_depth as i64 {
+                            settings.dirt
+                        } else {
+                            settings.stone
+                        };
+                        let pos = UVec3::new(x, y, z);
+                        assert_eq!(octree.get(pos), expected, "seed {} at {}", seed, pos);
+                        counts[expected as usize] += 1;
+                    }
+                }
+            }
+            // Every material shows up, so the surface runs through the tree
+            assert!(counts.iter().all(|count| *count > 0), "seed {}: {:?}", seed, counts);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "has to be between 1 and")]
+    fn rejects_trees_deeper_than_the_limit() {
+        HeightmapNoiseBuilder::new(MAX_HEIGHTMAP_DEPTH + 1, HeightmapSettings::default());
+    }
+
+    #[test]
+    #[should_panic(expected = "has to be between 1 and")]
+    fn rejects_empty_trees() {
+        HeightmapNoiseBuilder::new(0, HeightmapSettings::default());
+    }
+}
\ No newline at end of file
@@ -2,6 +2,7 @@
 
 use crate::*;
 use crate::octree::*;
+use crate::types::*;
 
 use bytemuck::*;
 use lazy_static::*;
@@ -9,53 +10,6 @@ use lazy_static::*;
 use std::*;
 
 
-struct Array3D2P<T> {
-    data: Vec<T>, 
-    size: u32,
-    size_power: u32
-}
-
-impl<T> Array3D2P<T> {
-    pub fn new(element: T, size: u32) -> Self where T: Clone {
-        let len = size * size * size;
-        let mut data: Vec<T> = Vec::with_capacity(len as usize);
-        let size_power: u32 = Self::exact_log(size);
-
-        for i in 0..len {
-            data.push(element.clone());
-        }
-
-        Array3D2P { data, size, size_power }
-    }
-
-    pub fn get(&self, pos: glam::UVec3) -> &T {
-        self.data.get(Self::compute_index(self.size_power, pos)).unwrap()
-    }
-    
-    pub fn get_mut(&mut self, pos: glam::UVec3) -> &mut T {
-        self.data.get_mut(Self::compute_index(self.size_power, pos)).unwrap()
-    }
-
-
-    pub unsafe fn get_unchecked(&self, pos: glam::UVec3) -> &T {
-        self.data.get_unchecked(Self::compute_index(self.size_power, pos))
-    }
-
-    pub unsafe fn get_unchecked_mut(&mut self, pos: glam::UVec3) -> &mut T { 
-        self.data.get_unchecked_mut(Self::compute_index(self.size_power, pos))
-    }
-
-    fn compute_index(power: u32, pos: glam:: UVec3) -> usize {
-        ((pos.z << (power << 1)) | (pos.y << power) | pos.x) as usize
-    }
-
-    fn exact_log(n: u32) -> u32 {
-        let pow : u32 = n.trailing_zeros();
-        assert! (n.leading_zeros() + pow == 31, "The given size was not a power of 2."); 
-        pow
-    }
-}
-
 #[repr(C, packed)]
 #[derive(Debug, Copy, Clone, Zeroable, Pod)]
 struct OctreeRenderAttribute {
@@ -191,7 +145,7 @@ struct OctreeRenderBox {
 impl OctreeRenderBox {
     const XY_MASK_TABLE: [[[[u64; 4]; 4]; 2]; 2] = Self::generate_xy_mask_lookup_table();
 
-    pub fn create(position: glam::UVec3, faces: Direction, partials: Direction, map: &Array3D2P<OctreeBoxEntry>) -> Option<OctreeRenderBox> {
+    pub fn create(position: glam::UVec3, faces: Direction, octree: &VoxelOctree, map: &Array3D2P<OctreeBoxEntry>) -> Option<OctreeRenderBox> {
         unsafe {
             if faces == Direction::NONE {
                 None
@@ -201,13 +155,16 @@ impl OctreeRenderBox {
                 let box_position = position >> const_uvec3!([3, 3, 3]);
 
                 Self::fill_mask_array(map.get_unchecked(box_position).branch.as_ref().unwrap_unchecked(), position, &mut voxels, &mut aabb);
-                let extents = Self::clip_face_extents(box_position, faces, partials, aabb, map);
+                let aabb = aabb?;
+                let solid_min = glam::uvec3(aabb.x as u32, aabb.y as u32, aabb.z as u32);
+                let solid_max = glam::uvec3(aabb.u as u32, aabb.v as u32, aabb.w as u32);
+                let extents = octree.clip_face_extents(position, 3, solid_min, solid_max, |material| material == 0);
 
                 if let Some(ext) = extents {
                     Some(OctreeRenderBox {
                         position,
                         faces: ext,
-                        aabb: aabb.unwrap_unchecked(),
+                        aabb,
                         voxels,
                     })
                 } else {
@@ -299,112 +256,4 @@ pub fn add_faces_to_buffer(
             material_mask,
         ));
     }
-}
-
-#[inline(always)]
-unsafe fn clip_face_extents(
-    box_position: glam::UVec3,
-    faces: Direction,
-    partials: Direction,
-    aabb: Option<BoundingBox>,
-    map: &Array3D2P<OctreeBoxEntry>,
-) -> Option<[Option<FaceExtent>; 6]> {
-    let aabb = aabb?;
-
-    let mut extents: [Option<FaceExtent>; 6] = [None; 6];
-
-    let aabb_face = FaceExtent::new_with_points(aabb.y, aabb.z, aabb.v, aabb.w);
-    if aabb.x == 0 {
-        if partials.contains(Direction::LEFT) {
-            let pos = box_position - const_uvec3!([1, 0, 0]);
-            extents[0] = Self::get_incoming_partial_extent(Direction::Right, aabb_face, map.get_unchecked(pos));
-        } else if faces.contains(Direction::LEFT) {
-            extents[0] = Some(aabb_face);
-        }
-    } else {
-        extents[0] = Some(aabb_face);
-    }
-    if aabb.u == 8 {
-        if partials.contains(Direction::LEFT) {
-            let pos = box_position - const_uvec3!([1, 0, 0]);
-            extents[1] = Self::get_incoming_partial_extent(Direction::LEFT, aabb_face, map.get_unchecked(pos));
-        } else if faces.contains(Direction::LEFT) {
-            extents[1] = Some(aabb_face);
-        }
-    } else {
-        extents[1] = Some(aabb_face);
-    }
-
-    let aabb_face = FaceExtent::new_with_points(aabb.x, aabb.z, aabb.u, aabb.w);
-    if aabb.y == 0 {
-        if partials.contains(Direction::DOWN) {
-            let pos = box_position - const_uvec3!([0, 1, 0]);
-            extents[2] = Self::get_incoming_partial_extent(Direction::Right, aabb_face, map.get_unchecked(pos));
-        } else if faces.contains(Direction::DOWN) {
-            extents[2] = Some(aabb_face);
-        }
-    } else {
-        extents[2] = Some(aabb_face);
-    }
-    if aabb.u == 8 {
-        if partials.contains(Direction::UP) {
-            let pos = box_position - const_uvec3!([0, 1, 0]);
-            extents[3] = Self::get_incoming_partial_extent(Direction::LEFT, aabb_face, map.get_unchecked(pos));
-        } else if faces.contains(Direction::UP) {
-            extents[3] = Some(aabb_face);
-        }
-    } else {
-        extents[3] = Some(aabb_face);
-    }
-
-    let aabb_face = FaceExtent::new_with_points(aabb.x, aabb.y, aabb.u, aabb.v);
-    if aabb.z == 0 {
-        if partials.contains(Direction::BACK) {
-            let pos = box_position - const_uvec3!([0, 0, 1]);
-            extents[4] = Self::get_incoming_partial_extent(Direction::FRONT, aabb_face, map.get_unchecked(pos));
-        } else if faces.contains(Direction::BACK) {
-            extents[4] = Some(aabb_face);
-        }
-    } else {
-        extents[4] = Some(aabb_face);
-    }
-    if aabb.w == 8 {
-        if partials.contains(Direction::FRONT) {
-            let pos = box_position + const_uvec3!([0, 0, 1]);
-            extents[5] = Self::get_incoming_partial_extent(Direction::BACK, aabb_face, map.get_unchecked(pos));
-        } else if faces.contains(Direction::FRONT) {
-            extents[5] = Some(aabb_face);
-        }
-    } else {
-        extents[5] = Some(aabb_face);
-    }
-
-    if extents.iter().any(|x| x.is_some()) {
-        Some(extents)
-    } else {
-        None
-    }
-}
-
-#[inline(always)]
-fn get_incoming_partial_extent(direction: Direction, extent: FaceExtent, entry: &OctreeBoxEntry) -> Option<FaceExtent> {
-    unsafe {
-        let mut result = None;
-        scan_branch_for_transparency(entry.branch.as_ref().unwrap_unchecked(), direction, extent, &mut result);
-        result
-    }
-}
-
-#[inline(always)]
-fn scan_branch_for_transparency(reader: &VoxelOctreeReader, face: Direction, constraint: FaceExtent, ext: &mut Option<FaceExtent>) {
-    const FACE_TABLE: [[u8; 4]; 6] = [
-        [VoxelOctant::Z0Y0X0.bits(), VoxelOctant::Z0Y1X0.bits(), VoxelOctant::Z1Y0X0.bits(), VoxelOctant::Z1Y1X0.bits()],
-        [VoxelOctant::Z0Y0X1.bits(), VoxelOctant::Z0Y1X1.bits(), VoxelOctant::Z1Y0X1.bits(), VoxelOctant::Z1Y1X1.bits()],
-        [VoxelOctant::Z0Y0X0.bits(), VoxelOctant::Z0Y0X1.bits(), VoxelOctant::Z1Y0X0.bits(), VoxelOctant::Z1Y0X1.bits()],
-        [VoxelOctant::Z0Y1X0.bits(), VoxelOctant::Z0Y1X1.bits(), VoxelOctant::Z1Y1X0.bits(), VoxelOctant::Z1Y1X1.bits()],
-        [VoxelOctant::Z0Y0X0.bits(), VoxelOctant::Z0Y0X1.bits(), VoxelOctant::Z0Y1X0.bits(), VoxelOctant::Z0Y1X1.bits()],
-        [VoxelOctant::Z1Y0X0.bits(), VoxelOctant::Z1Y0X1.bits(), VoxelOctant::Z1Y1X0.bits(), VoxelOctant::Z1Y1X1.bits()],
-    ];
-
-    // TODO: Continue script
 }
\ No newline at end of file
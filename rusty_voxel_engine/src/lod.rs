@@ -0,0 +1,171 @@
+// lod.rs
+use crate::octree::*;
+use crate::types::*;
+
+// How the eight voxels of a 2x2x2 block collapse into one voxel of a coarser tree
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LodPolicy<T = u16> {
+    // The most common material wins, ties go to the first one in VoxelOctant order
+    Majority,
+    // The material listed first wins, materials missing from the table only win by majority
+    // when none of the listed ones is present
+    Priority(Vec<T>),
+    // Any material other than the given empty one wins over it, by majority among themselves
+    SolidWins(T),
+}
+
+impl<T: VoxelPayload> LodPolicy<T> {
+    pub fn collapse(&self, materials: &[T; 8]) -> T {
+        match self {
+            LodPolicy::Majority => Self::majority(materials.iter().copied()).unwrap(),
+            LodPolicy::Priority(table) => table.iter().copied()
+                .find(|mat| materials.contains(mat))
+                .unwrap_or_else(|| Self::majority(materials.iter().copied()).unwrap()),
+            LodPolicy::SolidWins(empty) => Self::majority(materials.iter().copied().filter(|mat| mat != empty)).unwrap_or(*empty),
+        }
+    }
+
+    fn majority(materials: impl Iterator<Item = T> + Clone) -> Option<T> {
+        let mut best = None;
+        let mut best_count = 0;
+        for mat in materials.clone() {
+            let count = materials.clone().filter(|m| *m == mat).count();
+            if count > best_count {
+                best = Some(mat);
+                best_count = count;
+            }
+        }
+        best
+    }
+}
+
+impl<T: VoxelPayload> VoxelOctree<T> {
+    // Creates a coarser copy of the tree, every block of 1 << level voxels per edge becomes a
+    // single voxel. Blocks collapse 2x2x2 at a time from the bottom up following policy, so
+    // homogeneous octants are taken over as they are and only mixed ones get sampled.
+    pub fn lod(&self, level: u32, policy: &LodPolicy<T>) -> VoxelOctree<T> {
+        assert!(level < self.tree_depth, "The LOD level has to be below the tree depth ({}).", self.tree_depth);
+
+        let mut buffer = self.new_stream(0);
+
+        let top = self.lod_slot(self.top_slot(), self.tree_depth, level, policy, &mut buffer);
+        self.write_top(&mut buffer, top);
+
+        self.with_layout(self.tree_depth - level, buffer)
+    }
+
+    // Copies the octant at level into buffer, octants at lod_level become a single material
+    fn lod_slot(&self, slot: OctreeSlot<T>, level: u32, lod_level: u32, policy: &LodPolicy<T>, buffer: &mut UnmanagedByteBuffer) -> OctreeSlot<T> {
+        let node = match slot {
+            OctreeSlot::Homogeneous(_) => return slot,
+            OctreeSlot::Node(node) => node,
+        };
+
+        if level == lod_level {
+            return OctreeSlot::Homogeneous(self.collapse(slot, policy));
+        }
+
+        let mut children = self.children(node);
+        for child in children.iter_mut() {
+            *child = self.lod_slot(*child, level - 1, lod_level, policy, buffer);
+        }
+
+        match Self::merged_material(&children) {
+            Some(record) => OctreeSlot::Homogeneous(record),
+            None => OctreeSlot::Node(self.write_node(buffer, &children)),
+        }
+    }
+
+    // The policy picks the material, the layers come from the first child holding it
+    fn collapse(&self, slot: OctreeSlot<T>, policy: &LodPolicy<T>) -> VoxelRecord<T> {
+        match slot {
+            OctreeSlot::Homogeneous(record) => record,
+            OctreeSlot::Node(node) => {
+                let records = self.children(node).map(|child| self.collapse(child, policy));
+                let material = policy.collapse(&records.map(|record| record.material));
+                *records.iter().find(|record| record.material == material).unwrap()
+            }
+        }
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::*;
+    use crate::octree::tests::random_array;
+    use glam::UVec3;
+
+    // 4^3 tree whose first 2x2x2 block holds block in VoxelOctant order, the rest is fill
+    fn block_tree(block: [u16; 8], fill: u16) -> VoxelOctree {
+        let mut array = multiarray::Array3D::new([4, 4, 4], fill);
+        for (i, mat) in block.iter().enumerate() {
+            let pos = VoxelOctant::from_index(i).to_offset();
+            array[[pos.x as usize, pos.y as usize, pos.z as usize]] = *mat;
+        }
+        VoxelOctree::from_builder(&Array3DOctreeBuilder::new(&array, 0))
+    }
+
+    fn assert_collapses(policy: LodPolicy, block: [u16; 8], expected: u16) {
+        assert_eq!(policy.collapse(&block), expected, "{:?} of {:?}", policy, block);
+        let lod = block_tree(block, 9).lod(1, &policy);
+        assert_eq!(lod.tree_depth(), 1);
+        assert_eq!(lod.get(UVec3::ZERO), expected, "{:?} of {:?} in a tree", policy, block);
+        assert_eq!(lod.get(UVec3::ONE), 9);
+    }
+
+    #[test]
+    fn majority_takes_the_most_common_material() {
+        assert_collapses(LodPolicy::Majority, [2, 1, 2, 3, 2, 1, 3, 3], 2);
+        // Ties go to the material coming first
+        assert_collapses(LodPolicy::Majority, [1, 2, 2, 1, 3, 2, 1, 4], 1);
+        assert_collapses(LodPolicy::Majority, [4, 5, 6, 7, 1, 2, 3, 8], 4);
+    }
+
+    #[test]
+    fn priority_takes_the_first_listed_material() {
+        let policy = LodPolicy::Priority(vec![5, 3]);
+        assert_collapses(policy.clone(), [1, 1, 1, 1, 1, 3, 2, 5], 5);
+        assert_collapses(policy.clone(), [1, 1, 1, 1, 1, 3, 2, 2], 3);
+        // Nothing listed is present, the majority wins
+        assert_collapses(policy, [1, 2, 2, 4, 2, 1, 1, 2], 2);
+    }
+
+    #[test]
+    fn solid_wins_over_empty() {
+        assert_collapses(LodPolicy::SolidWins(0), [0, 0, 0, 0, 0, 0, 7, 0], 7);
+        assert_collapses(LodPolicy::SolidWins(0), [0, 0, 0, 4, 0, 7, 7, 4], 4);
+        assert_collapses(LodPolicy::SolidWins(0), [0, 6, 0, 4, 6, 7, 0, 0], 6);
+        assert_collapses(LodPolicy::SolidWins(0), [0; 8], 0);
+    }
+
+    // Collapses the octant at pos of edge length 1 << level voxel by voxel
+    fn brute_force(array: &multiarray::Array3D<u16>, pos: UVec3, level: u32, policy: &LodPolicy) -> u16 {
+        if level == 0 {
+            return array[[pos.x as usize, pos.y as usize, pos.z as usize]];
+        }
+        let children = std::array::from_fn(|i| brute_force(array, pos + (VoxelOctant::from_index(i).to_offset() << (level - 1)), level - 1, policy));
+        policy.collapse(&children)
+    }
+
+    #[test]
+    fn matches_brute_force_downsampling() {
+        for (seed, blobby) in [(3, true), (8, false)] {
+            let array = random_array([32, 32, 32], seed, 4, blobby);
+            let octree = VoxelOctree::from_builder(&Array3DOctreeBuilder::new(&array, 0));
+            for policy in [LodPolicy::Majority, LodPolicy::Priority(vec![3, 1]), LodPolicy::SolidWins(0)] {
+                for level in 1..4 {
+                    let lod = octree.lod(level, &policy);
+                    assert_eq!(lod.verify_packed(), Ok(()));
+                    for x in 0..lod.size() {
+                        for y in 0..lod.size() {
+                            for z in 0..lod.size() {
+                                let pos = UVec3::new(x, y, z);
+                                assert_eq!(lod.get(pos), brute_force(&array, pos << level, level, &policy), "{:?} at level {}, {}", policy, level, pos);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
\ No newline at end of file
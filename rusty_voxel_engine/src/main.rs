@@ -1,13 +1,25 @@
 // main.rs
 #![feature(portable_simd)]
 
-/*
 pub mod octree;
+pub mod dag;
+pub mod snapshot;
+pub mod diff;
+pub mod lod;
+pub mod transform;
+pub mod csg;
+pub mod world;
+pub mod parallel;
+pub mod morton;
+pub mod heightmap;
+pub mod density;
+pub mod sdf;
+pub mod combinators;
+pub mod biome;
 pub mod builder;
 pub mod types;
-pub mod lab2;
+// pub mod lab2;
 pub mod util;
-*/
 
 pub fn main() {
     //Create instance
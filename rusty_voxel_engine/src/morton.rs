@@ -0,0 +1,159 @@
+// morton.rs
+use glam::UVec3;
+use std::simd::{LaneCount, Simd, SupportedLaneCount};
+
+// Z-order curve indices. The bits of a position are interleaved per level in VoxelOctant
+// order, y in the lowest bit, then z, then x, so the low three bits of an index are the
+// octant of the voxel inside of its 2x2x2 block and every further three bits the octant
+// one level up.
+
+// Largest coordinate a 64 bit index can hold on every axis
+pub const MORTON_MAX_COORD: u32 = (1 << 21) - 1;
+
+// Spreads the low 21 bits of v apart, two zero bits follow every one of them
+fn spread_bits(v: u32) -> u64 {
+    let mut v = v as u64 & 0x1f_ffff;
+    v = (v | v << 32) & 0x001f_0000_0000_ffff;
+    v = (v | v << 16) & 0x001f_0000_ff00_00ff;
+    v = (v | v << 8) & 0x100f_00f0_0f00_f00f;
+    v = (v | v << 4) & 0x10c3_0c30_c30c_30c3;
+    v = (v | v << 2) & 0x1249_2492_4924_9249;
+    v
+}
+
+// Inverse of spread_bits
+fn compact_bits(v: u64) -> u32 {
+    let mut v = v & 0x1249_2492_4924_9249;
+    v = (v | v >> 2) & 0x10c3_0c30_c30c_30c3;
+    v = (v | v >> 4) & 0x100f_00f0_0f00_f00f;
+    v = (v | v >> 8) & 0x001f_0000_ff00_00ff;
+    v = (v | v >> 16) & 0x001f_0000_0000_ffff;
+    v = (v | v >> 32) & 0x1f_ffff;
+    v as u32
+}
+
+pub fn morton_encode(pos: UVec3) -> u64 {
+    debug_assert!(pos.max_element() <= MORTON_MAX_COORD, "The position is too large for a Morton index.");
+    spread_bits(pos.y) | spread_bits(pos.z) << 1 | spread_bits(pos.x) << 2
+}
+
+pub fn morton_decode(index: u64) -> UVec3 {
+    UVec3::new(compact_bits(index >> 2), compact_bits(index), compact_bits(index >> 1))
+}
+
+// The same spreading on every lane, for coordinates below 1024 so that indices fit in 32 bits
+fn spread_bits_simd<const N: usize>(v: Simd<u32, N>) -> Simd<u32, N> where LaneCount<N>: SupportedLaneCount {
+    let mut v = v & Simd::splat(0x3ff);
+    v = (v | v << Simd::splat(16)) & Simd::splat(0x0300_00ff);
+    v = (v | v << Simd::splat(8)) & Simd::splat(0x0300_f00f);
+    v = (v | v << Simd::splat(4)) & Simd::splat(0x030c_30c3);
+    v = (v | v << Simd::splat(2)) & Simd::splat(0x0924_9249);
+    v
+}
+
+fn compact_bits_simd<const N: usize>(v: Simd<u32, N>) -> Simd<u32, N> where LaneCount<N>: SupportedLaneCount {
+    let mut v = v & Simd::splat(0x0924_9249);
+    v = (v | v >> Simd::splat(2)) & Simd::splat(0x030c_30c3);
+    v = (v | v >> Simd::splat(4)) & Simd::splat(0x0300_f00f);
+    v = (v | v >> Simd::splat(8)) & Simd::splat(0x0300_00ff);
+    v = (v | v >> Simd::splat(16)) & Simd::splat(0x3ff);
+    v
+}
+
+// Encodes N positions at once, every coordinate has to be below 1024
+pub fn morton_encode_simd<const N: usize>(x: Simd<u32, N>, y: Simd<u32, N>, z: Simd<u32, N>) -> Simd<u32, N> where LaneCount<N>: SupportedLaneCount {
+    spread_bits_simd(y) | spread_bits_simd(z) << Simd::splat(1) | spread_bits_simd(x) << Simd::splat(2)
+}
+
+// Decodes N indices at once into their x, y and z coordinates
+pub fn morton_decode_simd<const N: usize>(index: Simd<u32, N>) -> [Simd<u32, N>; 3] where LaneCount<N>: SupportedLaneCount {
+    [compact_bits_simd(index >> Simd::splat(2)), compact_bits_simd(index), compact_bits_simd(index >> Simd::splat(1))]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::{Array3D2POctreeBuilder, Array3DOctreeBuilder};
+    use crate::octree::tests::random_array;
+    use crate::types::{Array3D2P, Array3DLayout, VoxelOctant, VoxelOctree};
+
+    fn xorshift(state: &mut u64) -> u64 {
+        *state ^= *state << 13;
+        *state ^= *state >> 7;
+        *state ^= *state << 17;
+        *state
+    }
+
+    #[test]
+    fn encode_decode_round_trip() {
+        for v in 0..=MORTON_MAX_COORD {
+            let pos = UVec3::new(v, MORTON_MAX_COORD - v, v.reverse_bits() >> 11);
+            assert_eq!(morton_decode(morton_encode(pos)), pos);
+        }
+
+        let mut state = 5;
+        for _ in 0..100_000 {
+            let index = xorshift(&mut state) >> 1;
+            assert_eq!(morton_encode(morton_decode(index)), index);
+        }
+        assert_eq!(morton_encode(UVec3::splat(MORTON_MAX_COORD)), u64::MAX >> 1);
+    }
+
+    #[test]
+    fn low_bits_are_the_octant() {
+        for octant in VoxelOctant::iter_all() {
+            assert_eq!(morton_encode(octant.to_offset()), octant.index() as u64);
+            assert_eq!(morton_encode(octant.to_offset() + UVec3::new(6, 4, 2)) & 7, octant.index() as u64);
+        }
+    }
+
+    #[test]
+    fn simd_matches_scalar() {
+        let mut state = 9;
+        for v in (0..1024).step_by(8) {
+            let x = Simd::<u32, 8>::from_array(std::array::from_fn(|i| v + i as u32));
+            let y = Simd::from_array(std::array::from_fn(|_| xorshift(&mut state) as u32 % 1024));
+            let z = Simd::from_array(std::array::from_fn(|i| 1023 - v - i as u32));
+            let indices = morton_encode_simd(x, y, z);
+            let [dx, dy, dz] = morton_decode_simd(indices);
+
+            for i in 0..8 {
+                let pos = UVec3::new(x[i], y[i], z[i]);
+                assert_eq!(indices[i] as u64, morton_encode(pos), "encoding {}", pos);
+                assert_eq!(UVec3::new(dx[i], dy[i], dz[i]), pos);
+            }
+        }
+    }
+
+    #[test]
+    fn layouts_round_trip() {
+        let array = Array3D2P::from_array(&random_array([16, 16, 16], 4, 6, false), 0u16, Array3DLayout::Linear);
+        let morton = array.to_layout(Array3DLayout::Morton);
+        assert_eq!(morton.layout(), Array3DLayout::Morton);
+
+        for x in 0..16 {
+            for y in 0..16 {
+                for z in 0..16 {
+                    let pos = UVec3::new(x, y, z);
+                    assert_eq!(morton.get(pos), array.get(pos));
+                    assert_eq!(morton.as_slice()[morton_encode(pos) as usize], *array.get(pos));
+                }
+            }
+        }
+        assert_eq!(morton.to_layout(Array3DLayout::Linear).as_slice(), array.as_slice());
+    }
+
+    #[test]
+    fn array3d2p_builder_matches_array3d_builder() {
+        for seed in 1..4 {
+            let array = random_array([32, 32, 32], seed, 4, seed != 2);
+            let expected = VoxelOctree::from_builder(&Array3DOctreeBuilder::new(&array, 0));
+
+            for layout in [Array3DLayout::Linear, Array3DLayout::Morton] {
+                let array = Array3D2P::from_array(&array, 0u16, layout);
+                let octree = VoxelOctree::from_builder(&Array3D2POctreeBuilder::new(&array));
+                assert_eq!(octree.buffer().as_slice(), expected.buffer().as_slice(), "{:?} layout", layout);
+            }
+        }
+    }
+}
\ No newline at end of file
@@ -2,27 +2,11 @@
 use crate::builder::*;
 use crate::types::*;
 
-use std::simd::*;
-use bitflags::*;
 use glam::*;
 use std::iter::*;
+use std::collections::HashMap;
 
-impl UnmanagedByteBuffer {
-    pub fn new_with_capacity(capacity: usize) -> Self {
-        let data = unsafe { std::alloc::alloc(std::alloc::Layout::from_size_align(capacity, std::mem::align_of::<u8>()).unwrap()) };
-        Self { tree_data, capacity }
-    }
-
-    pub fn get_allocation(&self, position: usize) -> *mut u8 {
-        assert!(position < self.capacity);
-        unsafe { self.data.add(position) } 
-    }
-
-    pub fn drop_allocation(&mut self) {
-        unsafe { std::alloc::dealloc(self.data, std::alloc::Layout::from_size_align(self.capacity, std::mem::align_of::<u8>()).unwrap()) };
-    }
-}
-
+/* // needs ray, intersection and vertex buffer types
 impl OctreeNode {
     fn traverse(&self, ray: &Ray, vertex_buffer: &mut VertexBuffer) -> Option<Intersection> {
         // Traversal and intersection code...
@@ -84,6 +68,7 @@ impl Voxel {
         vertices
     }   
 }
+*/
 
 /* // needs translated to rust
 impl Vertex {
@@ -133,65 +118,1811 @@ impl OctreeBuilder<u16> for VoxelOctreeBuilder {
         self.tree_depth
     }
 
-    fn get_octant(&self, pos: &OctreeCreationPosition, buffer: &mut UnmanagedByteBuffer, state: &u16) -> OctreeBuilderResult<u16> {
-        // Fetch the 8 voxels in this octant
-        let voxels = self.get_voxels_in_octant(pos);
-        
-        // Check if all voxels are the same. If they are, return Homogeneous with the common value.
-        if voxels.iter().all(|&v| v == voxels[0]) {
-            return OctreeBuilderResult::Homogeneous(voxels[0]);
+    fn get_octant(&self, pos: &OctreeCreationPosition, _buffer: &mut UnmanagedByteBuffer, state: &u16) -> OctreeBuilderResult<u16> {
+        // Octants outside of the voxel data are filled with the default material
+        if !array_contains_octant(&self.voxel_data, pos) {
+            return OctreeBuilderResult::Homogeneous(*state);
+        }
+
+        // Everything else gets subdivided, identical blocks are merged while building the tree
+        OctreeBuilderResult::Sparse
+    }
+
+    fn get_block(&self, pos: &OctreeCreationPosition, _buffer: &mut UnmanagedByteBuffer, state: &u16) -> [u16; 8] {
+        // Fetch the 8 voxels in this block
+        read_array_block(&self.voxel_data, *state, pos.position())
+    }
+}
+
+impl<T> OctreeRegion<T> {
+    // Edge length of the region in voxels
+    pub fn size(&self) -> u32 {
+        1 << self.level
+    }
+
+    // Exclusive upper corner of the region
+    pub fn max(&self) -> UVec3 {
+        self.min + UVec3::splat(self.size())
+    }
+}
+
+// Contents of one octant of the tree
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub(crate) enum OctreeSlot<T = u16> {
+    // The octant is a single record and has no node of its own
+    Homogeneous(VoxelRecord<T>),
+    // The octant is subdivided, its node starts at the given position
+    Node(u32),
+}
+
+// Deepest tree a VoxelOctreeReader can walk, coordinates have to fit into a UVec3
+pub const MAX_TREE_DEPTH: u32 = 31;
+
+// Most words a record can take, the largest payload and every layer
+pub(crate) const MAX_RECORD_WORDS: usize = MAX_PAYLOAD_WORDS + MAX_LAYERS;
+
+// Most words a node can take, a header and eight records of the largest size
+pub(crate) const MAX_NODE_WORDS: usize = 1 + 8 * MAX_RECORD_WORDS;
+
+// A cursor over the packed node stream of a VoxelOctree. Descending below a homogeneous
+// octant is allowed and yields the same material down to single voxels.
+pub struct VoxelOctreeReader<'a, T: VoxelPayload = u16> {
+    octree: &'a VoxelOctree<T>,
+    stack: [OctreeSlot<T>; MAX_TREE_DEPTH as usize + 1],
+    depth: usize,
+    position: UVec3,
+}
+
+impl<'a, T: VoxelPayload> VoxelOctreeReader<'a, T> {
+    pub fn new(octree: &'a VoxelOctree<T>) -> Self {
+        let mut stack = [OctreeSlot::Node(0); MAX_TREE_DEPTH as usize + 1];
+        stack[0] = octree.top_slot();
+        Self { octree, stack, depth: 0, position: UVec3::ZERO }
+    }
+
+    pub fn octree(&self) -> &'a VoxelOctree<T> {
+        self.octree
+    }
+
+    // Number of descents from the root
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    // log2 of the edge length of the current octant
+    pub fn level(&self) -> u32 {
+        self.octree.tree_depth - self.depth as u32
+    }
+
+    pub fn size(&self) -> u32 {
+        1 << self.level()
+    }
+
+    // Lower corner of the current octant
+    pub fn min(&self) -> UVec3 {
+        self.position
+    }
+
+    // Exclusive upper corner of the current octant
+    pub fn max(&self) -> UVec3 {
+        self.position + UVec3::splat(self.size())
+    }
+
+    // Octant of the current node inside of its parent
+    pub fn octant(&self) -> VoxelOctant {
+        if self.depth == 0 {
+            VoxelOctant::Z0Y0X0
+        } else {
+            VoxelOctant::containing(self.position, self.level())
+        }
+    }
+
+    // A leaf is a homogeneous octant, it has no node of its own
+    pub fn is_leaf(&self) -> bool {
+        matches!(self.slot(), OctreeSlot::Homogeneous(_))
+    }
+
+    // Material of the current octant if it is homogeneous
+    pub fn material(&self) -> Option<T> {
+        self.record().map(|record| record.material)
+    }
+
+    // Value of a layer in the current octant if it is homogeneous
+    pub fn layer(&self, layer: VoxelLayerId) -> Option<u16> {
+        self.record().map(|record| record.layers[layer])
+    }
+
+    pub fn record(&self) -> Option<VoxelRecord<T>> {
+        match self.slot() {
+            OctreeSlot::Homogeneous(record) => Some(record),
+            OctreeSlot::Node(_) => None,
+        }
+    }
+
+    // Position of the current node in the buffer if it is subdivided
+    pub fn node(&self) -> Option<u32> {
+        match self.slot() {
+            OctreeSlot::Homogeneous(_) => None,
+            OctreeSlot::Node(node) => Some(node),
+        }
+    }
+
+    pub(crate) fn slot(&self) -> OctreeSlot<T> {
+        self.stack[self.depth]
+    }
+
+    // Moves to the given child, returns false when already at a single voxel
+    pub fn descend(&mut self, octant: VoxelOctant) -> bool {
+        if self.level() == 0 {
+            return false;
+        }
+
+        let child = match self.slot() {
+            OctreeSlot::Homogeneous(_) => self.slot(),
+            OctreeSlot::Node(node) => self.octree.child_slot(node, octant.index()),
+        };
+
+        self.depth += 1;
+        self.stack[self.depth] = child;
+        self.position += octant.to_offset() << self.level();
+        true
+    }
+
+    // Moves to the parent, returns false when already at the root
+    pub fn ascend(&mut self) -> bool {
+        if self.depth == 0 {
+            return false;
+        }
+
+        let level = self.level();
+        self.position = (self.position >> (level + 1)) << (level + 1);
+        self.depth -= 1;
+        true
+    }
+
+    // Moves back to the root
+    pub fn reset(&mut self) {
+        self.depth = 0;
+        self.position = UVec3::ZERO;
+    }
+
+    // Moves to the octant of edge length 1 << level containing pos, stopping early at a
+    // homogeneous octant covering it. The current position is used as a starting point.
+    pub fn move_to(&mut self, pos: UVec3, level: u32) {
+        assert!(self.octree.contains(pos) && level <= self.octree.tree_depth, "The octant is outside of the octree.");
+
+        while self.level() < level || (pos >> self.level()) != (self.position >> self.level()) {
+            self.ascend();
+        }
+        while self.level() > level && !self.is_leaf() {
+            self.descend(VoxelOctant::containing(pos, self.level() - 1));
         }
-        
-        // If voxels are not all the same, further subdivide or declare it sparse.
-        // This depends on the level in the tree and other factors specific to your use case.
     }
 
-    fn get_block(&self, pos: &OctreeCreationPosition, buffer: &mut UnmanagedByteBuffer, state: &u16) -> u16x8 {
-        // Fetch the 8 voxels in this block and return them as a SIMD vector.
-        let voxels = self.get_voxels_in_block(pos);
-        return u16x8::new(voxels[0], voxels[1], voxels[2], voxels[3], voxels[4], voxels[5], voxels[6], voxels[7]);
+    // Moves to the neighbour of the current octant across the faces in directions: a single
+    // direction for a face neighbour, two for an edge and three for a corner neighbour. The
+    // neighbour is the octant of the same size, or the larger homogeneous octant covering it.
+    // Returns false and stays put when the neighbour lies outside of the tree.
+    pub fn move_to_neighbor(&mut self, directions: Direction) -> bool {
+        let step = directions.faces().fold(IVec3::ZERO, |step, face| step + face.to_ivec3());
+        // Opposite faces cancel out in step, so every face has to be left in it
+        assert!(directions != Direction::NONE && directions.faces().count() as i32 == step.abs().dot(IVec3::ONE), "The directions have to contain at least one face and no opposite faces.");
+
+        let tree_size = self.octree.size() as i64;
+        let target = [0, 1, 2].map(|i| self.position[i] as i64 + step[i] as i64 * self.size() as i64);
+        if target.iter().any(|t| *t < 0 || *t >= tree_size) {
+            return false;
+        }
+
+        self.move_to(UVec3::new(target[0] as u32, target[1] as u32, target[2] as u32), self.level());
+        true
     }
-    
 }
 
-impl VoxelOctree<T> {
-    // Creates a new voxel octree
-    pub fn new(tree_depth: u32, buffer: UnmanagedByteBuffer<u8>) -> Self {
-        Self { tree_depth, buffer }
-    }  
+// Depth first iterator over the homogeneous octants of a VoxelOctree, yielding
+// (min, size_log2, material). When clipped, octants crossing the clip box are split so
+// only cubes fully inside of it are yielded.
+pub struct VoxelOctreeRegions<'a, T: VoxelPayload = u16> {
+    reader: VoxelOctreeReader<'a, T>,
+    next_child: [u8; MAX_TREE_DEPTH as usize + 1],
+    arrived: bool,
+    done: bool,
+    min: UVec3,
+    max: UVec3,
+    material: Option<T>,
+}
+
+impl<'a, T: VoxelPayload> VoxelOctreeRegions<'a, T> {
+    pub fn new(octree: &'a VoxelOctree<T>) -> Self {
+        Self {
+            reader: octree.reader(),
+            next_child: [0; MAX_TREE_DEPTH as usize + 1],
+            arrived: true,
+            done: false,
+            min: UVec3::ZERO,
+            max: UVec3::splat(octree.size()),
+            material: None,
+        }
+    }
+
+    // Only yields octants of the given material
+    pub fn with_material(mut self, material: T) -> Self {
+        self.material = Some(material);
+        self
+    }
+
+    // Only yields the parts of the tree inside [min, max)
+    pub fn clipped(mut self, min: UVec3, max: UVec3) -> Self {
+        self.min = self.min.max(min);
+        self.max = self.max.min(max);
+        self
+    }
+
+    fn pop(&mut self) {
+        if !self.reader.ascend() {
+            self.done = true;
+        }
+    }
+}
+
+impl<'a, T: VoxelPayload> Iterator for VoxelOctreeRegions<'a, T> {
+    type Item = (UVec3, u32, T);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while !self.done {
+            if self.arrived {
+                self.arrived = false;
+                let (lo, hi) = (self.reader.min(), self.reader.max());
+
+                if lo.max(self.min).cmpge(hi.min(self.max)).any() {
+                    self.pop();
+                    continue;
+                }
+
+                if let Some(mat) = self.reader.material() {
+                    let wanted = self.material.map_or(true, |m| m == mat);
+                    if lo.cmpge(self.min).all() && hi.cmple(self.max).all() {
+                        let item = (lo, self.reader.level(), mat);
+                        self.pop();
+                        if wanted {
+                            return Some(item);
+                        }
+                        continue;
+                    }
+                    if !wanted {
+                        self.pop();
+                        continue;
+                    }
+                }
 
-    // WIP Creates a new voxel octree from an octree builder
-    pub fn from_builder<t>(builder: &impl OctreeBuilder<T>) -> Self {
+                self.next_child[self.reader.depth()] = 0;
+            }
+
+            let depth = self.reader.depth();
+            if self.next_child[depth] == 8 {
+                self.pop();
+                continue;
+            }
+
+            let octant = VoxelOctant::from_index(self.next_child[depth] as usize);
+            self.next_child[depth] += 1;
+            self.reader.descend(octant);
+            self.arrived = true;
+        }
+
+        None
+    }
+}
+
+impl<T: VoxelPayload> VoxelOctree<T> {
+    const PAYLOAD_WORDS: usize = {
+        assert!(T::WORDS > 0 && T::WORDS <= MAX_PAYLOAD_WORDS, "A payload has to take between 1 and MAX_PAYLOAD_WORDS words.");
+        T::WORDS
+    };
+
+    // Creates a new voxel octree without any layers
+    pub fn new(tree_depth: u32, buffer: UnmanagedByteBuffer) -> Self {
+        Self { tree_depth, buffer, garbage: 0, frozen: Vec::new(), frozen_len: 0, top: 0, layers: Vec::new(), payload: std::marker::PhantomData }
+    }
+
+    // Creates a new voxel octree from an octree builder
+    pub fn from_builder<S>(builder: &impl OctreeBuilder<S, T>) -> Self {
         let tree_depth = builder.get_tree_depth();
+        assert!(tree_depth > 0 && tree_depth <= MAX_TREE_DEPTH, "The tree depth has to be between 1 and {}.", MAX_TREE_DEPTH);
+
+        let mut octree = Self::new(tree_depth, UnmanagedByteBuffer::new());
+
+        // Reserve the top slot, the root is written last
+        let mut buffer = octree.new_stream(0);
+
+        let res = octree.create_octree_data_linear(&OctreeCreationPosition::new(UVec3::ZERO, tree_depth, VoxelOctant::Z0Y0X0), builder, &mut buffer, &builder.default_state());
 
-        unsafe {
-            let mut buffer = UnmanageedByteBuffer::<u8>::new_with_capacity(((2 << (3 * tree_depth)) >> 5) as usize);
-            let res = VoxelOctree::create_octree_data_linear(OctreeCreationPosition::new(new::Vec3(0,0,0), tree_depth, VoxelOctant::Z0Y0X0), builder, &mut buffer, &builder.default_state());
+        octree.write_top(&mut buffer, res);
+        octree.buffer = buffer;
+        octree
+    }
+
+    // Creates a tree of the given depth filled with a single material
+    pub fn filled(tree_depth: u32, material: T) -> Self {
+        assert!(tree_depth > 0 && tree_depth <= MAX_TREE_DEPTH, "The tree depth has to be between 1 and {}.", MAX_TREE_DEPTH);
+
+        let mut octree = Self::new(tree_depth, UnmanagedByteBuffer::new());
+        let mut buffer = octree.new_stream(0);
+        octree.write_top(&mut buffer, OctreeSlot::Homogeneous(VoxelRecord::new(material)));
+        octree.buffer = buffer;
+        octree
+    }
+
+    // Creates a tree with the same layers as this one around a node stream written for it
+    pub(crate) fn with_layout(&self, tree_depth: u32, buffer: UnmanagedByteBuffer) -> Self {
+        let mut octree = Self::new(tree_depth, buffer);
+        octree.layers = self.layers.clone();
+        octree
+    }
+
+    // Returns an empty node stream with room for the top slot
+    pub(crate) fn new_stream(&self, capacity: usize) -> UnmanagedByteBuffer {
+        let mut buffer = UnmanagedByteBuffer::new_with_capacity(capacity);
+        buffer.extend_from_slice(&[0; MAX_RECORD_WORDS + 1][..self.top_len()]);
+        buffer
+    }
+
+    // Words taken by a homogeneous slot
+    pub(crate) fn record_words(&self) -> usize {
+        Self::PAYLOAD_WORDS + self.layers.len()
+    }
+
+    // Words taken by the top slot, at least as many as a pointer
+    pub(crate) fn top_len(&self) -> usize {
+        1 + std::cmp::max(self.record_words(), 2)
+    }
+
+    pub fn tree_depth(&self) -> u32 {
+        self.tree_depth
+    }
 
-            if let Some(mat) = res.matrial {
-                self::fill_homogeneous_top(res.flags, mat, tree_depth, &mut buffer);
+    // The words written since the last snapshot. This is the whole node stream only while
+    // no snapshot shares nodes with the tree, e.g. right after building or compacting it.
+    pub fn buffer(&self) -> &UnmanagedByteBuffer {
+        &self.buffer
+    }
+
+    // Number of words in the node stream, including the ones shared with snapshots
+    pub fn stream_len(&self) -> usize {
+        self.frozen_len + self.buffer.count()
+    }
+
+    // Edge length of the octree in voxels
+    pub fn size(&self) -> u32 {
+        1 << self.tree_depth
+    }
+
+    pub fn contains(&self, pos: UVec3) -> bool {
+        pos.max_element() < self.size()
+    }
+
+    // Returns the material of the voxel at pos
+    pub fn get(&self, pos: UVec3) -> T {
+        self.find_homogeneous(pos).material
+    }
+
+    // Returns the value of a layer at pos
+    pub fn get_layer(&self, pos: UVec3, layer: VoxelLayerId) -> u16 {
+        assert!(layer < self.layers.len(), "The layer {} isn't registered.", layer);
+        self.get_record(pos).layers[layer]
+    }
+
+    // Returns the material and every layer of the voxel at pos
+    pub fn get_record(&self, pos: UVec3) -> VoxelRecord<T> {
+        assert!(self.contains(pos), "The position is outside of the octree.");
+        match self.slot_at(pos, 0) {
+            OctreeSlot::Homogeneous(record) => record,
+            OctreeSlot::Node(_) => unreachable!("A single voxel is always homogeneous."),
+        }
+    }
+
+    pub fn layers(&self) -> &[VoxelLayer] {
+        &self.layers
+    }
+
+    // Looks up a layer by name
+    pub fn layer(&self, name: &str) -> Option<VoxelLayerId> {
+        self.layers.iter().position(|layer| layer.name == name)
+    }
+
+    // Registers a new attribute layer, every voxel starts out at default.
+    // The node stream gets rewritten since every homogeneous slot grows by a word.
+    pub fn add_layer(&mut self, name: &str, default: u16) -> VoxelLayerId {
+        assert!(self.layers.len() < MAX_LAYERS, "A VoxelOctree can't have more than {} layers.", MAX_LAYERS);
+        assert!(self.layer(name).is_none(), "The layer {} is already registered.", name);
+
+        let id = self.layers.len();
+        let mut octree = self.with_layout(self.tree_depth, UnmanagedByteBuffer::new());
+        octree.layers.push(VoxelLayer { name: name.to_string(), default });
+
+        let mut buffer = octree.new_stream(self.live_len() + self.live_len() / 2);
+        let top = octree.import_slot(self, self.top_slot(), &|mut record| {
+            record.layers[id] = default;
+            record
+        }, &mut buffer);
+        octree.write_top(&mut buffer, top);
+        octree.buffer = buffer;
+
+        *self = octree;
+        id
+    }
+
+    // Record of material with every layer at its default
+    pub(crate) fn default_record(&self, material: T) -> VoxelRecord<T> {
+        let mut record = VoxelRecord::new(material);
+        for (value, layer) in record.layers.iter_mut().zip(&self.layers) {
+            *value = layer.default;
+        }
+        record
+    }
+
+    // Returns a cursor positioned at the root of the tree
+    pub fn reader(&self) -> VoxelOctreeReader<'_, T> {
+        VoxelOctreeReader::new(self)
+    }
+
+    // Returns the largest homogeneous octant containing pos
+    pub fn find_homogeneous(&self, pos: UVec3) -> OctreeRegion<T> {
+        assert!(self.contains(pos), "The position is outside of the octree.");
+        let mut reader = self.reader();
+
+        loop {
+            if let Some(material) = reader.material() {
+                return OctreeRegion { min: reader.min(), level: reader.level(), material };
             }
+            reader.descend(VoxelOctant::containing(pos, reader.level() - 1));
+        }
+    }
+
+    // Returns a cursor at the octant of edge length 1 << level containing pos, or at the
+    // homogeneous octant covering it
+    pub fn reader_at(&self, pos: UVec3, level: u32) -> VoxelOctreeReader<'_, T> {
+        let mut reader = self.reader();
+        reader.move_to(pos, level);
+        reader
+    }
+
+    // Returns a cursor at the neighbour of the octant of edge length 1 << level at min across
+    // the faces in directions, see VoxelOctreeReader::move_to_neighbor
+    pub fn neighbor(&self, min: UVec3, level: u32, directions: Direction) -> Option<VoxelOctreeReader<'_, T>> {
+        assert!(self.contains(min) && level <= self.tree_depth && min % (1 << level) == UVec3::ZERO, "The octant isn't aligned or is outside of the octree.");
+
+        // The octant itself may lie inside of a larger homogeneous one
+        let mut reader = self.reader();
+        while reader.level() > level {
+            reader.descend(VoxelOctant::containing(min, reader.level() - 1));
+        }
+
+        reader.move_to_neighbor(directions).then_some(reader)
+    }
+
+    // Clips the faces of the solid voxels of the octant at min against its neighbours, in face
+    // index order. solid_min and solid_max bound the voxels of the octant that aren't
+    // transparent, relative to min and max exclusive. Faces inside of the octant are drawn
+    // whole, faces on its boundary only where the neighbour is transparent, shrunk to those
+    // voxels. Faces on the boundary of the tree are always drawn. None if no face is left.
+    pub fn clip_face_extents(&self, min: UVec3, level: u32, solid_min: UVec3, solid_max: UVec3, is_transparent: impl Fn(T) -> bool) -> Option<[Option<FaceExtent>; 6]> {
+        let size = 1 << level;
+        assert!(level < 8, "Face extents only hold octants up to 128 voxels wide.");
+        assert!(solid_min.cmplt(solid_max).all() && solid_max.max_element() <= size, "The solid voxels are empty or outside of the octant.");
+        let mut extents = [None; 6];
+
+        for direction in Direction::FACES {
+            let axis = direction.axis() as usize;
+            let (a, b) = match direction.axis() {
+                Axis::X => (1, 2),
+                Axis::Y => (0, 2),
+                Axis::Z => (0, 1),
+            };
+            let face = FaceExtent::new_with_points(solid_min[a] as u8, solid_min[b] as u8, solid_max[a] as u8, solid_max[b] as u8);
+            let on_boundary = if direction.is_positive() { solid_max[axis] == size } else { solid_min[axis] == 0 };
+
+            if !on_boundary {
+                extents[direction.face_index()] = Some(face);
+                continue;
+            }
+
+            extents[direction.face_index()] = match self.neighbor(min, level, direction) {
+                None => Some(face),
+                Some(neighbor) => match neighbor.material() {
+                    Some(material) => is_transparent(material).then_some(face),
+                    None => {
+                        // Only the layer of the neighbour touching the face matters
+                        let mut pos = min;
+                        pos[axis] = if direction.is_positive() { min[axis] + size } else { min[axis] - 1 };
+                        let mut visible: Option<FaceExtent> = None;
+
+                        for i in face.x..face.u {
+                            for j in face.y..face.v {
+                                pos[a] = min[a] + i as u32;
+                                pos[b] = min[b] + j as u32;
+                                if is_transparent(self.get(pos)) {
+                                    let cell = FaceExtent::new_with_points(i, j, i + 1, j + 1);
+                                    visible = Some(visible.map_or(cell, |visible| visible.union(cell)));
+                                }
+                            }
+                        }
+                        visible
+                    }
+                },
+            };
+        }
 
-            return VoxelOctree {tree_depth, buffer };
+        extents.iter().any(Option::is_some).then_some(extents)
+    }
+
+    // Iterates over every homogeneous octant of the tree, including the voxels of leaf blocks
+    pub fn iter_regions(&self) -> VoxelOctreeRegions<'_, T> {
+        VoxelOctreeRegions::new(self)
+    }
+
+    // Copies the voxels in [min, max) into a cube whose origin is min. Cells past max are left
+    // at the payload of all zero words, 0 for the integer payloads.
+    pub fn get_region(&self, min: UVec3, max: UVec3) -> Array3D2P<T> {
+        self.get_region_with_layout(min, max, Array3DLayout::Linear)
+    }
+
+    pub fn get_region_with_layout(&self, min: UVec3, max: UVec3, layout: Array3DLayout) -> Array3D2P<T> {
+        assert!(min.cmple(max).all() && max.max_element() <= self.size(), "The region is outside of the octree.");
+        let zero = T::from_words(&[0; MAX_PAYLOAD_WORDS][..T::WORDS]);
+        let mut region = Array3D2P::new_with_layout(zero, (max - min).max_element().next_power_of_two(), layout);
+
+        for (pos, size_log2, mat) in self.iter_regions().clipped(min, max) {
+            let size = 1 << size_log2;
+            for z in pos.z..pos.z + size {
+                for y in pos.y..pos.y + size {
+                    for x in pos.x..pos.x + size {
+                        *region.get_mut(uvec3(x, y, z) - min) = mat;
+                    }
+                }
+            }
         }
+
+        region
+    }
+
+    // Copies the whole tree into a cube of its size
+    pub fn to_array3d2p(&self, layout: Array3DLayout) -> Array3D2P<T> {
+        self.get_region_with_layout(UVec3::ZERO, UVec3::splat(self.size()), layout)
     }
 
-    // Verifies the octree
+    pub fn to_array(&self) -> multiarray::Array3D<T> {
+        self.to_array3d2p(Array3DLayout::Linear).to_array()
+    }
+
+    pub(crate) fn top_slot(&self) -> OctreeSlot<T> {
+        let flags = NodeFlags::from_bits_retain((self.word(self.top) >> 8) as u8);
+        if flags.contains(NodeFlags::HOMOGENEOUS) {
+            OctreeSlot::Homogeneous(self.record(self.top + 1))
+        } else {
+            OctreeSlot::Node(self.read_pointer(self.top + 1))
+        }
+    }
+
+    // Reads a word of the node stream, frozen words live in the segments shared with snapshots
+    pub(crate) fn word(&self, position: usize) -> u16 {
+        if position >= self.frozen_len {
+            return self.buffer.get(position - self.frozen_len);
+        }
+
+        let segment = self.frozen.partition_point(|(start, _)| *start <= position) - 1;
+        let (start, words) = &self.frozen[segment];
+        words.get(position - start)
+    }
+
+    fn set_word(&mut self, position: usize, value: u16) {
+        assert!(position >= self.frozen_len, "Words shared with a snapshot can't be changed.");
+        self.buffer.set(position - self.frozen_len, value);
+    }
+
+    // Reads the payload and the layers of a homogeneous slot
+    pub(crate) fn record(&self, position: usize) -> VoxelRecord<T> {
+        let mut words = [0; MAX_PAYLOAD_WORDS];
+        for (i, word) in words[..T::WORDS].iter_mut().enumerate() {
+            *word = self.word(position + i);
+        }
+
+        let mut record = VoxelRecord::new(T::from_words(&words[..T::WORDS]));
+        for (i, value) in record.layers[..self.layers.len()].iter_mut().enumerate() {
+            *value = self.word(position + T::WORDS + i);
+        }
+        record
+    }
+
+    fn write_record(&self, record: VoxelRecord<T>, words: &mut [u16]) {
+        record.material.to_words(&mut words[..T::WORDS]);
+        words[T::WORDS..self.record_words()].copy_from_slice(&record.layers[..self.layers.len()]);
+    }
+
+    // Reads all eight child slots of a node
+    pub(crate) fn children(&self, node: u32) -> [OctreeSlot<T>; 8] {
+        let mut slots = [OctreeSlot::Node(0); 8];
+        for (i, slot) in slots.iter_mut().enumerate() {
+            *slot = self.child_slot(node, i);
+        }
+        slots
+    }
+
+    // Number of words taken by a node
+    pub(crate) fn node_len(&self, node: u32) -> usize {
+        self.slot_position(node, self.word(node as usize) as u8, 8) - node as usize
+    }
+
+    // Reads the slot of the child with the given VoxelOctant index
+    pub(crate) fn child_slot(&self, node: u32, index: usize) -> OctreeSlot<T> {
+        let mask = self.word(node as usize) as u8;
+        let position = self.slot_position(node, mask, index);
+        if mask & (1 << index) != 0 {
+            OctreeSlot::Node(self.read_pointer(position))
+        } else {
+            OctreeSlot::Homogeneous(self.record(position))
+        }
+    }
+
+    // Returns the slot of the octant at level containing pos, or of the homogeneous octant above it
+    pub(crate) fn slot_at(&self, pos: UVec3, level: u32) -> OctreeSlot<T> {
+        let mut slot = self.top_slot();
+        let mut current = self.tree_depth;
+        while current > level {
+            match slot {
+                OctreeSlot::Homogeneous(_) => break,
+                OctreeSlot::Node(node) => {
+                    current -= 1;
+                    slot = self.child_slot(node, Self::child_index(pos, current));
+                }
+            }
+        }
+        slot
+    }
+
+    // Every slot before index takes a record, or two words for each subdivided child
+    pub(crate) fn slot_position(&self, node: u32, mask: u8, index: usize) -> usize {
+        let nodes = (mask & ((1u16 << index) - 1) as u8).count_ones() as usize;
+        node as usize + 1 + (index - nodes) * self.record_words() + nodes * 2
+    }
+
+    pub(crate) fn read_pointer(&self, position: usize) -> u32 {
+        self.word(position) as u32 | ((self.word(position + 1) as u32) << 16)
+    }
+
+    // VoxelOctant index of the child at level that contains pos
+    pub(crate) fn child_index(pos: UVec3, level: u32) -> usize {
+        VoxelOctant::containing(pos, level).index()
+    }
+
+    // Builds the octant at pos depth first and appends its node after all of its subdivided children
+    pub(crate) fn create_octree_data_linear<S>(&self, pos: &OctreeCreationPosition, builder: &impl OctreeBuilder<S, T>, buffer: &mut UnmanagedByteBuffer, state: &S) -> OctreeSlot<T> {
+        let mut slots = [OctreeSlot::Node(0); 8];
+
+        if pos.level() == 0 {
+            // A single voxel can't be subdivided
+            match builder.get_octant(pos, buffer, state) {
+                OctreeBuilderResult::Homogeneous(mat) => return OctreeSlot::Homogeneous(self.default_record(mat)),
+                _ => panic!("A single voxel has to be homogeneous."),
+            }
+        }
+
+        let child_state;
+        let state = match builder.get_octant(pos, buffer, state) {
+            OctreeBuilderResult::Homogeneous(mat) => return OctreeSlot::Homogeneous(self.default_record(mat)),
+            OctreeBuilderResult::Sparse => state,
+            OctreeBuilderResult::SamplingRequired(s) => {
+                child_state = s;
+                &child_state
+            }
+        };
+
+        if pos.level() == 1 {
+            let block = builder.get_block(pos, buffer, state);
+            for (slot, mat) in slots.iter_mut().zip(block) {
+                *slot = OctreeSlot::Homogeneous(self.default_record(mat));
+            }
+        } else {
+            for (i, slot) in slots.iter_mut().enumerate() {
+                let child = unsafe { pos.child_unchecked(VoxelOctant::from_index(i)) };
+                *slot = self.create_octree_data_linear(&child, builder, buffer, state);
+            }
+        }
+
+        // Eight identical materials collapse into their parent
+        if let Some(mat) = Self::merged_material(&slots) {
+            return OctreeSlot::Homogeneous(mat);
+        }
+
+        OctreeSlot::Node(self.write_node(buffer, &slots))
+    }
+
+    // Appends a node with the given children and returns its position
+    pub(crate) fn write_node(&self, buffer: &mut UnmanagedByteBuffer, slots: &[OctreeSlot<T>; 8]) -> u32 {
+        let (words, len) = self.node_words(slots);
+        let node = buffer.count();
+        buffer.extend_from_slice(&words[..len]);
+        node as u32
+    }
+
+    // Encodes a node with the given children, returns the words and how many of them are used
+    pub(crate) fn node_words(&self, slots: &[OctreeSlot<T>; 8]) -> ([u16; MAX_NODE_WORDS], usize) {
+        let mut words = [0u16; MAX_NODE_WORDS];
+        let mut len = 1;
+        let mut mask = 0u8;
+
+        for (i, slot) in slots.iter().enumerate() {
+            match *slot {
+                OctreeSlot::Homogeneous(record) => {
+                    self.write_record(record, &mut words[len..]);
+                    len += self.record_words();
+                }
+                OctreeSlot::Node(ptr) => {
+                    words[len] = ptr as u16;
+                    words[len + 1] = (ptr >> 16) as u16;
+                    len += 2;
+                    mask |= 1 << i;
+                }
+            }
+        }
+
+        words[0] = Self::node_header(NodeFlags::empty(), mask);
+        (words, len)
+    }
+
+    // Returns the record if all children are the same homogeneous record
+    pub(crate) fn merged_material(slots: &[OctreeSlot<T>; 8]) -> Option<VoxelRecord<T>> {
+        match slots[0] {
+            OctreeSlot::Homogeneous(first) if slots.iter().all(|s| *s == OctreeSlot::Homogeneous(first)) => Some(first),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn write_top(&self, buffer: &mut UnmanagedByteBuffer, slot: OctreeSlot<T>) {
+        let len = self.top_len();
+        buffer.as_mut_slice()[..len].copy_from_slice(&self.top_words(slot)[..len]);
+    }
+
+    fn top_words(&self, slot: OctreeSlot<T>) -> [u16; MAX_RECORD_WORDS + 1] {
+        let mut words = [0; MAX_RECORD_WORDS + 1];
+        match slot {
+            OctreeSlot::Homogeneous(record) => {
+                words[0] = Self::node_header(NodeFlags::HOMOGENEOUS, 0);
+                self.write_record(record, &mut words[1..]);
+            }
+            OctreeSlot::Node(root) => {
+                words[0] = Self::node_header(NodeFlags::empty(), 0);
+                words[1] = root as u16;
+                words[2] = (root >> 16) as u16;
+            }
+        }
+        words
+    }
+
+    // Appends a node to the live buffer and returns its position in the node stream
+    fn append_node(&mut self, slots: &[OctreeSlot<T>; 8]) -> u32 {
+        let (words, len) = self.node_words(slots);
+        let node = self.stream_len();
+        self.buffer.extend_from_slice(&words[..len]);
+        node as u32
+    }
+
+    pub(crate) fn node_header(flags: NodeFlags, mask: u8) -> u16 {
+        ((flags.bits() as u16) << 8) | mask as u16
+    }
+
+    // Sets the material of the voxel at pos, splitting and merging octants as needed
+    pub fn set(&mut self, pos: UVec3, material: T) {
+        self.edit_voxel(pos, |record| VoxelRecord { material, ..record });
+        self.compact_if_fragmented();
+    }
+
+    // Sets a batch of voxels, the buffer gets compacted at most once at the end
+    pub fn set_many(&mut self, edits: impl IntoIterator<Item = (UVec3, T)>) {
+        for (pos, material) in edits {
+            self.edit_voxel(pos, |record| VoxelRecord { material, ..record });
+        }
+        self.compact_if_fragmented();
+    }
+
+    // Fills the box between min and max (both inclusive) with material
+    pub fn fill(&mut self, min: UVec3, max: UVec3, material: T) {
+        self.edit_box(min, max, |record| VoxelRecord { material, ..record });
+        self.compact_if_fragmented();
+    }
+
+    // Sets the value of a layer at pos, the material and the other layers are kept
+    pub fn set_layer(&mut self, pos: UVec3, layer: VoxelLayerId, value: u16) {
+        assert!(layer < self.layers.len(), "The layer {} isn't registered.", layer);
+        self.edit_voxel(pos, |mut record| {
+            record.layers[layer] = value;
+            record
+        });
+        self.compact_if_fragmented();
+    }
+
+    // Sets the value of a layer in the box between min and max (both inclusive)
+    pub fn fill_layer(&mut self, min: UVec3, max: UVec3, layer: VoxelLayerId, value: u16) {
+        assert!(layer < self.layers.len(), "The layer {} isn't registered.", layer);
+        self.edit_box(min, max, |mut record| {
+            record.layers[layer] = value;
+            record
+        });
+        self.compact_if_fragmented();
+    }
+
+    // Replaces the record of the voxel at pos with edit applied to it
+    pub(crate) fn edit_voxel(&mut self, pos: UVec3, edit: impl Fn(VoxelRecord<T>) -> VoxelRecord<T>) {
+        assert!(self.contains(pos), "The position is outside of the octree.");
+
+        // Shared nodes can't be patched in place
+        if self.is_dag() {
+            self.expand_dag();
+        }
+
+        let top = self.top_slot();
+        let slot = self.set_slot(top, pos, self.tree_depth, &edit);
+        self.set_top(top, slot);
+    }
+
+    // Replaces every record in the box between min and max with edit applied to it
+    pub(crate) fn edit_box(&mut self, min: UVec3, max: UVec3, edit: impl Fn(VoxelRecord<T>) -> VoxelRecord<T>) {
+        assert!(min.cmple(max).all() && self.contains(max), "The box is outside of the octree.");
+
+        if self.is_dag() {
+            self.expand_dag();
+        }
+
+        let top = self.top_slot();
+        let slot = self.fill_slot(top, UVec3::ZERO, self.tree_depth, min, max, &edit);
+        self.set_top(top, slot);
+    }
+
+    fn set_top(&mut self, top: OctreeSlot<T>, slot: OctreeSlot<T>) {
+        if slot != top {
+            let len = self.top_len();
+            for (i, word) in self.top_words(slot)[..len].iter().copied().enumerate() {
+                self.set_word(self.top + i, word);
+            }
+        }
+    }
+
+    // Returns the new slot of the octant at level after editing the voxel at pos inside of it
+    fn set_slot(&mut self, slot: OctreeSlot<T>, pos: UVec3, level: u32, edit: &impl Fn(VoxelRecord<T>) -> VoxelRecord<T>) -> OctreeSlot<T> {
+        let mut children = match slot {
+            OctreeSlot::Homogeneous(record) => {
+                let edited = edit(record);
+                if edited == record {
+                    return slot;
+                }
+                if level == 0 {
+                    return OctreeSlot::Homogeneous(edited);
+                }
+                [slot; 8]
+            }
+            OctreeSlot::Node(node) => self.children(node),
+        };
+
+        let index = Self::child_index(pos, level - 1);
+        let child = self.set_slot(children[index], pos, level - 1, edit);
+        if child == children[index] {
+            return slot;
+        }
+
+        children[index] = child;
+        self.update_node(slot, &children)
+    }
+
+    // Returns the new slot of the octant of edge length 1 << level at octant_min after
+    // editing the part of it inside the box between min and max. Homogeneous octants inside
+    // of the box are edited as a whole, nodes are edited child by child since their records
+    // can differ in any layer.
+    fn fill_slot(&mut self, slot: OctreeSlot<T>, octant_min: UVec3, level: u32, min: UVec3, max: UVec3, edit: &impl Fn(VoxelRecord<T>) -> VoxelRecord<T>) -> OctreeSlot<T> {
+        let octant_max = octant_min + UVec3::splat((1 << level) - 1);
+        if octant_max.cmplt(min).any() || octant_min.cmpgt(max).any() {
+            return slot;
+        }
+
+        let mut children = match slot {
+            OctreeSlot::Homogeneous(record) => {
+                let edited = edit(record);
+                if edited == record {
+                    return slot;
+                }
+                if octant_min.cmpge(min).all() && octant_max.cmple(max).all() {
+                    return OctreeSlot::Homogeneous(edited);
+                }
+                [slot; 8]
+            }
+            OctreeSlot::Node(node) => self.children(node),
+        };
+
+        let mut changed = false;
+        for (i, child) in children.iter_mut().enumerate() {
+            let child_min = octant_min + (VoxelOctant::from_index(i).to_offset() << (level - 1));
+            let new_child = self.fill_slot(*child, child_min, level - 1, min, max, edit);
+            changed |= new_child != *child;
+            *child = new_child;
+        }
+
+        if changed {
+            self.update_node(slot, &children)
+        } else {
+            slot
+        }
+    }
+
+    // Returns the slot holding children in place of slot. Nodes keeping their child mask are
+    // patched in place unless a snapshot shares them, everything else is appended.
+    fn update_node(&mut self, slot: OctreeSlot<T>, children: &[OctreeSlot<T>; 8]) -> OctreeSlot<T> {
+        if let Some(record) = Self::merged_material(children) {
+            if let OctreeSlot::Node(node) = slot {
+                self.garbage += self.node_len(node);
+            }
+            return OctreeSlot::Homogeneous(record);
+        }
+
+        let (words, len) = self.node_words(children);
+        match slot {
+            OctreeSlot::Node(node) if node as usize >= self.frozen_len && self.word(node as usize) == words[0] => {
+                for (i, word) in words[..len].iter().enumerate() {
+                    self.set_word(node as usize + i, *word);
+                }
+                slot
+            }
+            OctreeSlot::Node(node) => {
+                self.garbage += self.node_len(node);
+                OctreeSlot::Node(self.append_node(children))
+            }
+            OctreeSlot::Homogeneous(_) => OctreeSlot::Node(self.append_node(children)),
+        }
+    }
+
+    // Words in the node stream that are still referenced by the tree
+    pub fn live_len(&self) -> usize {
+        self.stream_len() - self.garbage
+    }
+
+    // Rewrites the tree into a freshly packed buffer, dropping every node left behind by edits.
+    // Shared nodes of a DAG are copied once per reference, so the result is always a plain tree.
+    pub fn compact(&mut self) {
+        let mut buffer = self.new_stream(self.live_len());
+
+        let top = self.copy_slot(self.top_slot(), &mut buffer);
+        self.write_top(&mut buffer, top);
+
+        self.replace_buffer(buffer);
+    }
+
+    // Swaps in a freshly written node stream with its top slot at the start.
+    // Frozen segments are released, snapshots keep their own references to them.
+    pub(crate) fn replace_buffer(&mut self, buffer: UnmanagedByteBuffer) {
+        self.buffer = buffer;
+        self.frozen.clear();
+        self.frozen_len = 0;
+        self.top = 0;
+        self.garbage = 0;
+    }
+
+    pub(crate) fn compact_if_fragmented(&mut self) {
+        if self.garbage > self.live_len() {
+            self.compact();
+        }
+    }
+
+    // Copies a subtree into buffer in the same order from_builder writes it
+    pub(crate) fn copy_slot(&self, slot: OctreeSlot<T>, buffer: &mut UnmanagedByteBuffer) -> OctreeSlot<T> {
+        match slot {
+            OctreeSlot::Homogeneous(_) => slot,
+            OctreeSlot::Node(node) => {
+                let mut children = self.children(node);
+                for child in children.iter_mut() {
+                    *child = self.copy_slot(*child, buffer);
+                }
+                OctreeSlot::Node(self.write_node(buffer, &children))
+            }
+        }
+    }
+
+    // Copies a subtree of source into buffer laid out for this tree, mapping every record.
+    // Octants whose children end up identical are merged.
+    pub(crate) fn import_slot(&self, source: &VoxelOctree<T>, slot: OctreeSlot<T>, map: &impl Fn(VoxelRecord<T>) -> VoxelRecord<T>, buffer: &mut UnmanagedByteBuffer) -> OctreeSlot<T> {
+        match slot {
+            OctreeSlot::Homogeneous(record) => OctreeSlot::Homogeneous(map(record)),
+            OctreeSlot::Node(node) => {
+                let mut children = source.children(node);
+                for child in children.iter_mut() {
+                    *child = self.import_slot(source, *child, map, buffer);
+                }
+                match Self::merged_material(&children) {
+                    Some(record) => OctreeSlot::Homogeneous(record),
+                    None => OctreeSlot::Node(self.write_node(buffer, &children)),
+                }
+            }
+        }
+    }
+
+    // Verifies the octree. Nodes left behind by edits are allowed as long as garbage accounts
+    // for them, verify_packed also rejects those.
     pub fn verify(&self) -> Result<(), VoxelOctreeVerificationError> {
-        unsafe {
-            if self.verify_level(0, self. tree_depth)? == self.buffer().count() {
-                Ok(())
+        self.verify_unused(self.garbage)
+    }
+
+    // Verifies the octree and that every word of the node stream is in use, which holds right
+    // after from_builder or compact
+    pub fn verify_packed(&self) -> Result<(), VoxelOctreeVerificationError> {
+        self.verify_unused(0)
+    }
+
+    fn verify_unused(&self, unused: usize) -> Result<(), VoxelOctreeVerificationError> {
+        let mut used = vec![false; self.stream_len()];
+        let mut path = Vec::with_capacity(self.tree_depth as usize);
+        let mut visited = HashMap::new();
+
+        if self.verify_top(&mut path, &mut used, &mut visited)? + unused == self.stream_len() {
+            Ok(())
+        }
+        else {
+            Err(VoxelOctreeVerificationError::NonPackedOctree(used.iter().position(|u| !u).unwrap_or(0)))
+        }
+    }
+
+    // Checks the top slot and everything below it, returns the number of words in use
+    fn verify_top(&self, path: &mut Vec<VoxelOctant>, used: &mut [bool], visited: &mut HashMap<u32, u32>) -> Result<usize, VoxelOctreeVerificationError> {
+        let top = self.top;
+        let len = self.top_len();
+        if top + len > self.stream_len() {
+            return Err(VoxelOctreeVerificationError::BufferOverrun { node: top, path: path.clone() });
+        }
+        used[top..top + len].fill(true);
+
+        // Words past the payload or the pointer are padding and have to be zero
+        let header = self.word(top);
+        let flags = NodeFlags::from_bits((header >> 8) as u8);
+        let padding = |used: usize| (top + 1 + used..top + len).any(|i| self.word(i) != 0);
+        let bad_flags = match flags {
+            Some(flags) if flags.contains(NodeFlags::HOMOGENEOUS) => header as u8 != 0 || padding(self.record_words()),
+            Some(_) => header as u8 != 0 || padding(2),
+            None => true,
+        };
+        if bad_flags {
+            return Err(VoxelOctreeVerificationError::BadFlags { node: top, path: path.clone(), header });
+        }
+
+        match self.top_slot() {
+            OctreeSlot::Homogeneous(_) => Ok(len),
+            OctreeSlot::Node(root) => {
+                self.verify_pointer(top, root, path)?;
+                Ok(len + self.verify_level(root, self.tree_depth, path, used, visited)?)
+            }
+        }
+    }
+
+    // Checks the node at level and its subtree, returns the number of words it takes.
+    // Nodes of a DAG are shared, they only count once and are only checked again when
+    // they show up at a lower level than before.
+    fn verify_level(&self, node: u32, level: u32, path: &mut Vec<VoxelOctant>, used: &mut [bool], visited: &mut HashMap<u32, u32>) -> Result<usize, VoxelOctreeVerificationError> {
+        let revisit = match visited.get(&node) {
+            Some(&lowest) if lowest <= level => return Ok(0),
+            Some(_) => true,
+            None => false,
+        };
+        if self.is_dag() {
+            visited.insert(node, level);
+        }
+
+        let header = self.word(node as usize);
+        if header >> 8 != 0 || (level == 1 && header as u8 != 0) {
+            return Err(VoxelOctreeVerificationError::BadFlags { node: node as usize, path: path.clone(), header });
+        }
+
+        let start = node as usize;
+        let end = start + self.node_len(node);
+        if end > self.stream_len() {
+            return Err(VoxelOctreeVerificationError::BufferOverrun { node: start, path: path.clone() });
+        }
+        if !revisit {
+            if used[start..end].iter().any(|u| *u) {
+                return Err(VoxelOctreeVerificationError::OverlappingNode { node: start, path: path.clone() });
             }
-            else {
-                Err(VoxelOctreeVerificationError::NonPackedOctree(self.buffer().count()))
+            used[start..end].fill(true);
+        }
+
+        let children = self.children(node);
+        if Self::merged_material(&children).is_some() {
+            return Err(VoxelOctreeVerificationError::NonCanonicalNode { node: start, path: path.clone() });
+        }
+
+        let mut count = if revisit { 0 } else { end - start };
+        for (i, child) in children.iter().enumerate() {
+            if let OctreeSlot::Node(ptr) = *child {
+                self.verify_pointer(start, ptr, path)?;
+                path.push(VoxelOctant::from_index(i));
+                count += self.verify_level(ptr, level - 1, path, used, visited)?;
+                path.pop();
             }
         }
+
+        Ok(count)
     }
+
+    // Snapshots move the top slot to the end of the stream, so it can be anywhere
+    fn verify_pointer(&self, node: usize, ptr: u32, path: &[VoxelOctant]) -> Result<(), VoxelOctreeVerificationError> {
+        let ptr = ptr as usize;
+        if (self.top..self.top + self.top_len()).contains(&ptr) || ptr >= self.stream_len() {
+            Err(VoxelOctreeVerificationError::ChildPointerOutOfRange { node, path: path.to_vec(), pointer: ptr as u32 })
+        } else {
+            Ok(())
+        }
+    }
+
     pub fn traverse_renderable(&self) {
         // Traverse nodes that are within the camera frustum
     }
 
     pub fn render(&self) {
         // Render the octree using the GPU
-    }   
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    // Array of the given extents filled from a xorshift sequence, blobby arrays have large
+    // homogeneous regions with a few random voxels in them
+    pub(crate) fn random_array(extents: [usize; 3], seed: u64, materials: u16, blobby: bool) -> multiarray::Array3D<u16> {
+        let mut array = multiarray::Array3D::new(extents, 0u16);
+        let mut state = seed.max(1);
+        let mut next = || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state
+        };
+        for x in 0..extents[0] {
+            for y in 0..extents[1] {
+                for z in 0..extents[2] {
+                    array[[x, y, z]] = if blobby {
+                        (((x / 4) ^ (y / 4) ^ (z / 8)) as u64 % materials as u64) as u16 + (next() % 50 == 0) as u16
+                    } else {
+                        (next() % materials as u64) as u16
+                    };
+                }
+            }
+        }
+        array
+    }
+
+    pub(crate) fn assert_matches_array(octree: &VoxelOctree, array: &multiarray::Array3D<u16>, default_material: u16) {
+        let extents = array.extents();
+        for x in 0..octree.size() {
+            for y in 0..octree.size() {
+                for z in 0..octree.size() {
+                    let (i, j, k) = (x as usize, y as usize, z as usize);
+                    let expected = if i < extents[0] && j < extents[1] && k < extents[2] { array[[i, j, k]] } else { default_material };
+                    assert_eq!(octree.get(UVec3::new(x, y, z)), expected, "at {}, {}, {}", x, y, z);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn round_trips_random_arrays() {
+        for (seed, materials, blobby) in [(1, 2, false), (7, 5, false), (3, 3, true), (11, 1, false)] {
+            let array = random_array([16, 16, 16], seed, materials, blobby);
+            let octree = VoxelOctree::from_builder(&Array3DOctreeBuilder::new(&array, 0));
+            assert_eq!(octree.tree_depth(), 4);
+            assert_matches_array(&octree, &array, 0);
+            assert_eq!(octree.verify(), Ok(()));
+        }
+    }
+
+    #[test]
+    fn round_trips_odd_sizes() {
+        for size in [1, 3, 5, 16] {
+            for extents in [[size, size, size], [size, 1, size], [1, size, 3]] {
+                let array = random_array(extents, size as u64, 3, false);
+                let octree = VoxelOctree::from_builder(&Array3DOctreeBuilder::new(&array, 9));
+                assert!(octree.size() as usize >= size);
+                assert_matches_array(&octree, &array, 9);
+                assert_eq!(octree.verify(), Ok(()));
+            }
+        }
+    }
+
+    #[test]
+    fn set_splits_and_merges_octants() {
+        let mut octree = VoxelOctree::filled(4, 0u16);
+        let edits = [UVec3::new(1, 2, 3), UVec3::new(15, 0, 7), UVec3::new(1, 2, 2)];
+        for (i, pos) in edits.iter().enumerate() {
+            octree.set(*pos, i as u16 + 1);
+            assert_eq!(octree.verify(), Ok(()));
+        }
+        for (i, pos) in edits.iter().enumerate() {
+            assert_eq!(octree.get(*pos), i as u16 + 1);
+        }
+        assert!(octree.garbage > 0);
+        assert!(matches!(octree.verify_packed(), Err(VoxelOctreeVerificationError::NonPackedOctree(_))));
+
+        // Setting the voxels back merges every octant again
+        for pos in edits {
+            octree.set(pos, 0);
+            assert_eq!(octree.verify(), Ok(()));
+        }
+        assert!(matches!(octree.top_slot(), OctreeSlot::Homogeneous(_)));
+        assert_eq!(octree.live_len(), octree.top_len());
+
+        octree.compact();
+        assert_eq!(octree.verify_packed(), Ok(()));
+        assert_eq!(octree.stream_len(), octree.top_len());
+    }
+
+    #[test]
+    fn set_many_matches_set_and_compacts() {
+        let array = random_array([16, 16, 16], 5, 3, true);
+        let edits: Vec<(UVec3, u16)> = (0..600u32).map(|i| (UVec3::new(i * 7 % 16, i * 3 % 16, i * 11 % 16), (i % 4) as u16)).collect();
+
+        let mut expected = random_array([16, 16, 16], 5, 3, true);
+        for (pos, material) in &edits {
+            expected[[pos.x as usize, pos.y as usize, pos.z as usize]] = *material;
+        }
+        let fresh = VoxelOctree::from_builder(&Array3DOctreeBuilder::new(&expected, 0));
+
+        let mut single = VoxelOctree::from_builder(&Array3DOctreeBuilder::new(&array, 0));
+        for (pos, material) in &edits {
+            single.set(*pos, *material);
+            assert_eq!(single.verify(), Ok(()));
+        }
+        let mut bulk = VoxelOctree::from_builder(&Array3DOctreeBuilder::new(&array, 0));
+        bulk.set_many(edits.iter().copied());
+        assert_eq!(bulk.verify(), Ok(()));
+        assert!(bulk.garbage <= bulk.live_len());
+
+        for octree in [&mut single, &mut bulk] {
+            assert_matches_array(octree, &expected, 0);
+            octree.compact();
+            assert_eq!(octree.verify_packed(), Ok(()));
+            assert_eq!(octree.buffer().as_slice(), fresh.buffer().as_slice());
+        }
+
+        // Clearing most of the tree leaves more garbage than live words, so the batch ends compacted
+        let mut cleared = random_array([16, 16, 16], 9, 4, false);
+        let mut octree = VoxelOctree::from_builder(&Array3DOctreeBuilder::new(&cleared, 0));
+        let edits: Vec<(UVec3, u16)> = (0..16 * 16 * 12).map(|i| (UVec3::new(i / 256, i / 16 % 16, i % 16), 0)).collect();
+        for (pos, material) in &edits {
+            cleared[[pos.x as usize, pos.y as usize, pos.z as usize]] = *material;
+        }
+        octree.set_many(edits);
+        assert_eq!(octree.garbage, 0);
+        assert_eq!(octree.verify_packed(), Ok(()));
+        assert_matches_array(&octree, &cleared, 0);
+    }
+
+    // 8^3 tree where every voxel has its own material, so no octant merges
+    fn distinct_voxels() -> VoxelOctree {
+        let mut array = multiarray::Array3D::new([8, 8, 8], 0u16);
+        for x in 0..8 {
+            for y in 0..8 {
+                for z in 0..8 {
+                    array[[x, y, z]] = (x * 64 + y * 8 + z) as u16 + 1;
+                }
+            }
+        }
+        VoxelOctree::from_builder(&Array3DOctreeBuilder::new(&array, 0))
+    }
+
+    fn step(directions: Direction) -> IVec3 {
+        directions.faces().fold(IVec3::ZERO, |step, face| step + face.to_ivec3())
+    }
+
+    #[test]
+    fn finds_face_edge_and_corner_neighbors_across_parents() {
+        let octree = distinct_voxels();
+        let faces = Direction::FACES;
+        let mut sets = Vec::new();
+        for a in 0..6 {
+            sets.push(faces[a]);
+            for b in a + 1..6 {
+                if faces[a].axis() != faces[b].axis() {
+                    sets.push(faces[a] | faces[b]);
+                    for c in b + 1..6 {
+                        if faces[c].axis() != faces[a].axis() && faces[c].axis() != faces[b].axis() {
+                            sets.push(faces[a] | faces[b] | faces[c]);
+                        }
+                    }
+                }
+            }
+        }
+        assert_eq!(sets.len(), 6 + 12 + 8);
+
+        for level in 0..3 {
+            let size = 1 << level;
+            // Octants on both sides of the centre cross the boundary of the root's children
+            for min in [UVec3::splat(4 - size), UVec3::splat(4), UVec3::new(4 - size, 4, 4 - size)] {
+                for directions in &sets {
+                    let target = min.as_ivec3() + step(*directions) * size as i32;
+                    let neighbor = octree.neighbor(min, level, *directions);
+                    if target.cmplt(IVec3::ZERO).any() || target.cmpge(IVec3::splat(8)).any() {
+                        assert!(neighbor.is_none());
+                        continue;
+                    }
+                    let neighbor = neighbor.unwrap();
+                    assert_eq!((neighbor.min(), neighbor.level()), (target.as_uvec3(), level), "{:?} of {} at level {}", directions, min, level);
+                    if level == 0 {
+                        assert_eq!(neighbor.material(), Some(octree.get(target.as_uvec3())));
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn neighbor_stops_at_larger_homogeneous_octants() {
+        let mut octree = VoxelOctree::filled(3, 0u16);
+        octree.set(UVec3::new(3, 3, 3), 1);
+        // The right neighbour of the voxel lies in the homogeneous child of the root next to it
+        let neighbor = octree.neighbor(UVec3::new(3, 3, 3), 0, Direction::RIGHT).unwrap();
+        let target = (UVec3::new(3, 3, 3).as_ivec3() + Direction::RIGHT.to_ivec3()).as_uvec3();
+        assert!(neighbor.level() > 0);
+        assert!(target.cmpge(neighbor.min()).all() && target.cmplt(neighbor.min() + UVec3::splat(neighbor.size())).all());
+        assert_eq!(neighbor.material(), Some(0));
+        assert!(octree.neighbor(UVec3::ZERO, 0, Direction::LEFT | Direction::DOWN | Direction::BACK).is_none());
+    }
+
+    #[test]
+    #[should_panic(expected = "no opposite faces")]
+    fn neighbor_rejects_opposite_faces() {
+        let octree = distinct_voxels();
+        octree.neighbor(UVec3::splat(2), 0, Direction::LEFT | Direction::RIGHT | Direction::UP);
+    }
+
+    #[test]
+    fn clips_faces_against_neighbors() {
+        let mut octree = VoxelOctree::filled(4, 1u16);
+        octree.fill(UVec3::new(7, 9, 10), UVec3::new(7, 11, 10), 0);
+        octree.set(UVec3::new(7, 12, 15), 0);
+
+        // Air next to the -x face, the tree ends on the positive sides and solid neighbours
+        // hide the rest
+        let whole = Some(FaceExtent::new_with_points(0, 0, 8, 8));
+        let extents = octree.clip_face_extents(UVec3::splat(8), 3, UVec3::ZERO, UVec3::splat(8), |m| m == 0).unwrap();
+        assert_eq!(extents, [Some(FaceExtent::new_with_points(1, 2, 5, 8)), whole, None, whole, None, whole]);
+
+        // Faces inside of the octant are drawn whole
+        let extents = octree.clip_face_extents(UVec3::ZERO, 3, UVec3::new(0, 2, 1), UVec3::new(3, 8, 8), |m| m == 0).unwrap();
+        assert_eq!(extents[Direction::LEFT.face_index()], Some(FaceExtent::new_with_points(2, 1, 8, 8)));
+        assert_eq!(extents[Direction::RIGHT.face_index()], Some(FaceExtent::new_with_points(2, 1, 8, 8)));
+        assert_eq!(extents[Direction::DOWN.face_index()], Some(FaceExtent::new_with_points(0, 1, 3, 8)));
+        assert_eq!(extents[Direction::UP.face_index()], None);
+
+        // An octant buried in solid voxels has nothing to draw
+        let octree = VoxelOctree::filled(5, 1u16);
+        assert_eq!(octree.clip_face_extents(UVec3::splat(8), 3, UVec3::ZERO, UVec3::splat(8), |m| m == 0), None);
+    }
+
+    #[test]
+    fn clipped_faces_match_brute_force() {
+        let array = random_array([32, 32, 32], 8, 3, true);
+        let octree = VoxelOctree::from_builder(&Array3DOctreeBuilder::new(&array, 0));
+        let transparent = |m: u16| m == 0;
+        let mut drawn = 0;
+
+        for level in 1..4 {
+            let size = 1 << level;
+            for x in (0..32).step_by(size as usize) {
+                for y in (0..32).step_by(size as usize) {
+                    for z in (0..32).step_by(size as usize) {
+                        let min = UVec3::new(x, y, z);
+                        let solid = (0..size * size * size)
+                            .map(|i| UVec3::new(i % size, i / size % size, i / size / size))
+                            .filter(|offset| !transparent(octree.get(min + *offset)));
+                        let Some((solid_min, solid_max)) = solid.fold(None, |bounds: Option<(UVec3, UVec3)>, offset| {
+                            Some(bounds.map_or((offset, offset + 1), |(lo, hi)| (lo.min(offset), hi.max(offset + 1))))
+                        }) else {
+                            continue;
+                        };
+
+                        let mut expected = [None; 6];
+                        for direction in Direction::FACES {
+                            let axis = direction.axis() as usize;
+                            let (a, b) = [(1, 2), (0, 2), (0, 1)][axis];
+                            let face = FaceExtent::new_with_points(solid_min[a] as u8, solid_min[b] as u8, solid_max[a] as u8, solid_max[b] as u8);
+                            let layer = if direction.is_positive() { min[axis] as i32 + size as i32 } else { min[axis] as i32 - 1 };
+                            let on_boundary = if direction.is_positive() { solid_max[axis] == size } else { solid_min[axis] == 0 };
+
+                            expected[direction.face_index()] = if !on_boundary || !(0..32).contains(&layer) {
+                                Some(face)
+                            } else {
+                                let mut visible: Option<FaceExtent> = None;
+                                for i in face.x..face.u {
+                                    for j in face.y..face.v {
+                                        let mut pos = min;
+                                        pos[axis] = layer as u32;
+                                        pos[a] += i as u32;
+                                        pos[b] += j as u32;
+                                        if array[[pos.x as usize, pos.y as usize, pos.z as usize]] == 0 {
+                                            let cell = FaceExtent::new_with_points(i, j, i + 1, j + 1);
+                                            visible = Some(visible.map_or(cell, |v| v.union(cell)));
+                                        }
+                                    }
+                                }
+                                visible
+                            };
+                        }
+
+                        let expected = expected.iter().any(Option::is_some).then_some(expected);
+                        assert_eq!(octree.clip_face_extents(min, level, solid_min, solid_max, transparent), expected, "octant {} at level {}", min, level);
+                        drawn += expected.is_some() as usize;
+                    }
+                }
+            }
+        }
+        assert!(drawn > 100);
+    }
+
+    // Overwrites a word of a tree that has no snapshot
+    fn corrupt(octree: &mut VoxelOctree, position: usize, value: u16) {
+        octree.buffer.set(position, value);
+    }
+
+    fn root(octree: &VoxelOctree) -> u32 {
+        match octree.top_slot() {
+            OctreeSlot::Node(root) => root,
+            OctreeSlot::Homogeneous(_) => panic!("The tree has no root node."),
+        }
+    }
+
+    fn child(octree: &VoxelOctree, node: u32, index: usize) -> u32 {
+        match octree.child_slot(node, index) {
+            OctreeSlot::Node(child) => child,
+            OctreeSlot::Homogeneous(_) => panic!("The child is homogeneous."),
+        }
+    }
+
+    #[test]
+    fn rejects_child_pointers_out_of_range() {
+        let mut octree = distinct_voxels();
+        let len = octree.stream_len() as u32;
+        corrupt(&mut octree, 1, len as u16);
+        corrupt(&mut octree, 2, (len >> 16) as u16);
+        assert_eq!(octree.verify(), Err(VoxelOctreeVerificationError::ChildPointerOutOfRange { node: 0, path: vec![], pointer: len }));
+
+        // Pointing back at the top slot is just as wrong
+        let mut octree = distinct_voxels();
+        let root = root(&octree);
+        let position = octree.slot_position(root, 0xff, 3);
+        corrupt(&mut octree, position, 0);
+        corrupt(&mut octree, position + 1, 0);
+        assert_eq!(octree.verify(), Err(VoxelOctreeVerificationError::ChildPointerOutOfRange { node: root as usize, path: vec![], pointer: 0 }));
+    }
+
+    #[test]
+    fn rejects_bad_flags() {
+        let mut octree = distinct_voxels();
+        let root = root(&octree);
+        let header = VoxelOctree::<u16>::node_header(NodeFlags::HOMOGENEOUS, 0xff);
+        corrupt(&mut octree, root as usize, header);
+        assert_eq!(octree.verify(), Err(VoxelOctreeVerificationError::BadFlags { node: root as usize, path: vec![], header }));
+
+        // Nodes at level 1 only hold voxels, they can't point to children
+        let mut octree = distinct_voxels();
+        let node = child(&octree, child(&octree, root, 2), 5);
+        corrupt(&mut octree, node as usize, 0x01);
+        let path = vec![VoxelOctant::from_index(2), VoxelOctant::from_index(5)];
+        assert_eq!(octree.verify(), Err(VoxelOctreeVerificationError::BadFlags { node: node as usize, path, header: 0x01 }));
+
+        // The top slot only knows the homogeneous flag
+        let mut octree = distinct_voxels();
+        corrupt(&mut octree, 0, 0x4000);
+        assert_eq!(octree.verify(), Err(VoxelOctreeVerificationError::BadFlags { node: 0, path: vec![], header: 0x4000 }));
+    }
+
+    #[test]
+    fn rejects_buffer_overruns() {
+        // The root is written last, claiming more children makes it run past the end
+        let mut array = multiarray::Array3D::new([8, 8, 8], 0u16);
+        array[[1, 2, 3]] = 1;
+        let mut octree = VoxelOctree::from_builder(&Array3DOctreeBuilder::new(&array, 0));
+        let root = root(&octree);
+        assert_eq!(root as usize + octree.node_len(root), octree.stream_len());
+        corrupt(&mut octree, root as usize, 0xff);
+        assert_eq!(octree.verify(), Err(VoxelOctreeVerificationError::BufferOverrun { node: root as usize, path: vec![] }));
+    }
+
+    #[test]
+    fn rejects_overlapping_nodes() {
+        let mut octree = distinct_voxels();
+        let root = root(&octree);
+        let shared = child(&octree, root, 0);
+        let position = octree.slot_position(root, 0xff, 1);
+        corrupt(&mut octree, position, shared as u16);
+        corrupt(&mut octree, position + 1, (shared >> 16) as u16);
+        assert_eq!(octree.verify(), Err(VoxelOctreeVerificationError::OverlappingNode { node: shared as usize, path: vec![VoxelOctant::from_index(1)] }));
+    }
+
+    #[test]
+    fn rejects_non_canonical_nodes() {
+        let mut octree = distinct_voxels();
+        let node = child(&octree, child(&octree, root(&octree), 7), 0);
+        for i in 0..8 {
+            let position = octree.slot_position(node, 0, i);
+            corrupt(&mut octree, position, 42);
+        }
+        let path = vec![VoxelOctant::from_index(7), VoxelOctant::from_index(0)];
+        assert_eq!(octree.verify(), Err(VoxelOctreeVerificationError::NonCanonicalNode { node: node as usize, path }));
+    }
+
+    #[test]
+    fn rejects_unused_words() {
+        let mut octree = distinct_voxels();
+        let len = octree.stream_len();
+        octree.buffer.add(0);
+        assert_eq!(octree.verify(), Err(VoxelOctreeVerificationError::NonPackedOctree(len)));
+    }
+
+    fn xorshift(state: &mut u64) -> u64 {
+        *state ^= *state << 13;
+        *state ^= *state >> 7;
+        *state ^= *state << 17;
+        *state
+    }
+
+    // Checks that the reader stands on an octant of the tree and that a leaf holds its material
+    fn assert_reader_matches_get(reader: &VoxelOctreeReader, octree: &VoxelOctree) {
+        let (min, size) = (reader.min(), reader.size());
+        assert_eq!(min % size, UVec3::ZERO, "{} isn't aligned to {}", min, size);
+        assert_eq!(reader.level() as usize + reader.depth(), octree.tree_depth() as usize);
+        if reader.depth() > 0 {
+            assert_eq!(reader.octant(), VoxelOctant::containing(min, reader.level()));
+        }
+
+        // Large octants only get their corners checked
+        let materials: Vec<u16> = if size <= 8 {
+            (0..size * size * size).map(|i| octree.get(min + UVec3::new(i / (size * size), i / size % size, i % size))).collect()
+        } else {
+            VoxelOctant::iter_all().map(|octant| octree.get(min + octant.to_offset() * (size - 1))).collect()
+        };
+        match reader.material() {
+            Some(mat) => assert!(materials.iter().all(|m| *m == mat), "leaf of {} at {} isn't homogeneous", mat, min),
+            // Nodes are canonical, they never hold a single material
+            None if size <= 8 => assert!(materials.iter().any(|m| *m != materials[0]), "node at {} is homogeneous", min),
+            None => {}
+        }
+    }
+
+    #[test]
+    fn reader_matches_get_after_random_moves() {
+        let array = random_array([32, 32, 32], 21, 3, true);
+        let octree = VoxelOctree::from_builder(&Array3DOctreeBuilder::new(&array, 0));
+        let mut reader = octree.reader();
+        let mut state = 5;
+        let mut neighbor_moves = 0;
+
+        for _ in 0..4000 {
+            let (min, level) = (reader.min(), reader.level());
+            match xorshift(&mut state) % 5 {
+                0 => {
+                    let octant = VoxelOctant::from_index(xorshift(&mut state) as usize % 8);
+                    let descended = reader.descend(octant);
+                    assert_eq!(descended, level > 0);
+                    if descended {
+                        assert_eq!((reader.min(), reader.level()), (min + (octant.to_offset() << (level - 1)), level - 1));
+                    }
+                }
+                1 => {
+                    let ascended = reader.ascend();
+                    assert_eq!(ascended, level < octree.tree_depth());
+                    if ascended {
+                        assert_eq!((reader.min(), reader.level()), ((min >> (level + 1)) << (level + 1), level + 1));
+                    }
+                }
+                2 => {
+                    let pos = UVec3::new(xorshift(&mut state) as u32 % 32, xorshift(&mut state) as u32 % 32, xorshift(&mut state) as u32 % 32);
+                    let target = xorshift(&mut state) as u32 % 6;
+                    reader.move_to(pos, target);
+                    assert!(pos.cmpge(reader.min()).all() && pos.cmplt(reader.max()).all());
+                    assert!(reader.level() == target || (reader.level() > target && reader.is_leaf()));
+                }
+                3 if xorshift(&mut state) % 50 == 0 => {
+                    reader.reset();
+                    assert_eq!((reader.min(), reader.depth(), reader.level()), (UVec3::ZERO, 0, octree.tree_depth()));
+                }
+                _ => {
+                    // A random face, edge or corner without opposite faces
+                    let mut directions = Direction::NONE;
+                    for axis in 0..3 {
+                        match xorshift(&mut state) % 3 {
+                            0 => directions |= Direction::FACES[axis * 2],
+                            1 => directions |= Direction::FACES[axis * 2 + 1],
+                            _ => {}
+                        }
+                    }
+                    if directions == Direction::NONE {
+                        continue;
+                    }
+
+                    let target = min.as_ivec3() + step(directions) * (1 << level);
+                    let moved = reader.move_to_neighbor(directions);
+                    if target.cmplt(IVec3::ZERO).any() || target.cmpge(IVec3::splat(32)).any() {
+                        assert!(!moved);
+                        assert_eq!((reader.min(), reader.level()), (min, level));
+                    } else {
+                        assert!(moved);
+                        let target = target.as_uvec3();
+                        assert!(target.cmpge(reader.min()).all() && target.cmplt(reader.max()).all());
+                        assert!(reader.level() == level || (reader.level() > level && reader.is_leaf()));
+                        // Count moves whose neighbour sits under another parent
+                        if level < octree.tree_depth() && target >> (level + 1) != min >> (level + 1) {
+                            neighbor_moves += 1;
+                        }
+                    }
+                }
+            }
+            assert_reader_matches_get(&reader, &octree);
+        }
+        assert!(neighbor_moves > 100, "only {} moves crossed a node boundary", neighbor_moves);
+    }
+
+    #[test]
+    fn reader_stops_at_the_ends_of_the_tree() {
+        let octree = distinct_voxels();
+        let mut reader = octree.reader();
+        assert!(!reader.ascend());
+        while reader.descend(VoxelOctant::Z1Y1X1) {}
+        assert_eq!((reader.min(), reader.level()), (UVec3::splat(7), 0));
+        assert_eq!(reader.material(), Some(octree.get(UVec3::splat(7))));
+        assert!(!reader.descend(VoxelOctant::Z0Y0X0));
+        assert!(!reader.move_to_neighbor(Direction::RIGHT));
+        assert!(reader.move_to_neighbor(Direction::LEFT | Direction::DOWN));
+        assert_eq!(reader.min(), UVec3::new(6, 6, 7));
+        reader.reset();
+        assert_eq!((reader.min(), reader.depth()), (UVec3::ZERO, 0));
+        assert!(reader.node().is_some());
+    }
+
+    // Counts how often each voxel is covered by the yielded regions, failing on ones outside of [min, max)
+    fn coverage(regions: impl Iterator<Item = (UVec3, u32, u16)>, array: &multiarray::Array3D<u16>, min: UVec3, max: UVec3) -> multiarray::Array3D<u8> {
+        let mut covered = multiarray::Array3D::new(array.extents(), 0u8);
+        for (lo, level, material) in regions {
+            let size = 1 << level;
+            assert!(lo.cmpge(min).all() && (lo + UVec3::splat(size)).cmple(max).all(), "region at {} of size {} leaves the clip box", lo, size);
+            for i in 0..size * size * size {
+                let pos = lo + UVec3::new(i / (size * size), i / size % size, i % size);
+                let (x, y, z) = (pos.x as usize, pos.y as usize, pos.z as usize);
+                assert_eq!(array[[x, y, z]], material, "at {}", pos);
+                covered[[x, y, z]] += 1;
+            }
+        }
+        covered
+    }
+
+    #[test]
+    fn clipped_regions_cover_the_box_exactly_once() {
+        let array = random_array([32, 32, 32], 17, 3, true);
+        let octree = VoxelOctree::from_builder(&Array3DOctreeBuilder::new(&array, 0));
+        let boxes = [
+            (UVec3::ZERO, UVec3::splat(32)),
+            (UVec3::new(3, 5, 7), UVec3::new(29, 17, 8)),
+            (UVec3::new(16, 0, 15), UVec3::new(17, 32, 31)),
+            (UVec3::new(8, 8, 8), UVec3::new(24, 24, 24)),
+            (UVec3::new(5, 5, 5), UVec3::new(5, 9, 9)),
+        ];
+
+        for (min, max) in boxes {
+            let inside = |x: usize, y: usize, z: usize| UVec3::new(x as u32, y as u32, z as u32).cmpge(min).all() && UVec3::new(x as u32, y as u32, z as u32).cmplt(max).all();
+            let covered = coverage(octree.iter_regions().clipped(min, max), &array, min, max);
+            for x in 0..32 {
+                for y in 0..32 {
+                    for z in 0..32 {
+                        assert_eq!(covered[[x, y, z]], inside(x, y, z) as u8, "at {}, {}, {} in {} to {}", x, y, z, min, max);
+                    }
+                }
+            }
+
+            // Filtering by material yields exactly the voxels of that material
+            for material in 0..4 {
+                let covered = coverage(octree.iter_regions().clipped(min, max).with_material(material), &array, min, max);
+                for x in 0..32 {
+                    for y in 0..32 {
+                        for z in 0..32 {
+                            let expected = inside(x, y, z) && array[[x, y, z]] == material;
+                            assert_eq!(covered[[x, y, z]], expected as u8, "material {} at {}, {}, {}", material, x, y, z);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn assert_layer_matches(octree: &VoxelOctree, layer: VoxelLayerId, expected: &multiarray::Array3D<u16>) {
+        for x in 0..octree.size() {
+            for y in 0..octree.size() {
+                for z in 0..octree.size() {
+                    let pos = UVec3::new(x, y, z);
+                    let value = expected[[x as usize, y as usize, z as usize]];
+                    assert_eq!(octree.get_layer(pos, layer), value, "layer {} at {}", layer, pos);
+                    assert_eq!(octree.reader_at(pos, 0).layer(layer), Some(value), "reader for layer {} at {}", layer, pos);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn adding_layers_keeps_materials() {
+        let array = random_array([16, 16, 16], 6, 3, true);
+        let mut octree = VoxelOctree::from_builder(&Array3DOctreeBuilder::new(&array, 0));
+        let light = octree.add_layer("light", 15);
+        let biome = octree.add_layer("biome", 2);
+        assert_eq!((octree.layer("light"), octree.layer("biome"), octree.layer("water")), (Some(light), Some(biome), None));
+        assert_eq!(octree.verify_packed(), Ok(()));
+
+        assert_matches_array(&octree, &array, 0);
+        assert_layer_matches(&octree, light, &multiarray::Array3D::new([16, 16, 16], 15));
+        assert_layer_matches(&octree, biome, &multiarray::Array3D::new([16, 16, 16], 2));
+    }
+
+    #[test]
+    fn set_and_fill_layer_read_back() {
+        let array = random_array([16, 16, 16], 12, 3, true);
+        let mut octree = VoxelOctree::from_builder(&Array3DOctreeBuilder::new(&array, 0));
+        let light = octree.add_layer("light", 0);
+        let biome = octree.add_layer("biome", 7);
+        let mut lights = multiarray::Array3D::new([16, 16, 16], 0u16);
+        let biomes = multiarray::Array3D::new([16, 16, 16], 7u16);
+
+        let mut state = 3;
+        for i in 0..300 {
+            let pos = UVec3::new(xorshift(&mut state) as u32 % 16, xorshift(&mut state) as u32 % 16, xorshift(&mut state) as u32 % 16);
+            octree.set_layer(pos, light, i % 16);
+            lights[[pos.x as usize, pos.y as usize, pos.z as usize]] = i % 16;
+        }
+        octree.fill_layer(UVec3::new(2, 3, 4), UVec3::new(9, 15, 5), light, 99);
+        for x in 2..=9 {
+            for y in 3..=15 {
+                for z in 4..=5 {
+                    lights[[x, y, z]] = 99;
+                }
+            }
+        }
+        assert_eq!(octree.verify(), Ok(()));
+
+        assert_matches_array(&octree, &array, 0);
+        assert_layer_matches(&octree, light, &lights);
+        assert_layer_matches(&octree, biome, &biomes);
+    }
+
+    #[test]
+    fn layer_edits_split_homogeneous_octants() {
+        let mut octree = VoxelOctree::filled(4, 3u16);
+        let light = octree.add_layer("light", 0);
+        assert!(octree.reader().is_leaf());
+
+        octree.set_layer(UVec3::new(5, 6, 7), light, 9);
+        octree.fill_layer(UVec3::new(8, 0, 0), UVec3::new(15, 7, 7), light, 4);
+        assert!(!octree.reader().is_leaf());
+        assert_eq!(octree.verify(), Ok(()));
+        assert_matches_array(&octree, &multiarray::Array3D::new([16, 16, 16], 3), 3);
+        assert_eq!(octree.get_layer(UVec3::new(5, 6, 7), light), 9);
+        assert_eq!(octree.get_layer(UVec3::new(5, 6, 6), light), 0);
+        assert_eq!(octree.get_layer(UVec3::new(12, 1, 2), light), 4);
+        // The filled octant stays a single homogeneous slot
+        assert_eq!(octree.reader_at(UVec3::new(8, 0, 0), 3).layer(light), Some(4));
+
+        // Resetting the layer merges everything back into the top slot
+        octree.set_layer(UVec3::new(5, 6, 7), light, 0);
+        octree.fill_layer(UVec3::new(8, 0, 0), UVec3::new(15, 7, 7), light, 0);
+        assert!(octree.reader().is_leaf());
+        assert_eq!(octree.reader().material(), Some(3));
+        assert_eq!(octree.verify(), Ok(()));
+    }
+
+    #[test]
+    fn merges_homogeneous_arrays() {
+        let array = multiarray::Array3D::new([16, 16, 16], 4u16);
+        let octree = VoxelOctree::from_builder(&Array3DOctreeBuilder::new(&array, 4));
+        assert_eq!(octree.stream_len(), octree.top_len());
+        assert_matches_array(&octree, &array, 4);
+        assert_eq!(octree.verify(), Ok(()));
+    }
 }
\ No newline at end of file
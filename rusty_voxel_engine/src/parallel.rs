@@ -0,0 +1,157 @@
+// parallel.rs
+use crate::builder::*;
+use crate::octree::*;
+use crate::types::*;
+
+use glam::UVec3;
+use rayon::prelude::*;
+
+// Levels below the root that get decided serially before the work is split, up to 8^3
+// subtrees are built in parallel
+const PARALLEL_LEVELS: u32 = 3;
+
+// The top levels of a tree as sampled before the parallel build
+enum PlannedOctant<T> {
+    Homogeneous(T),
+    // Built by a worker, the index of its task
+    Task(usize),
+    Split(Box<[PlannedOctant<T>; 8]>),
+}
+
+// Octants left for the workers, each one with the index of its builder state
+struct BuildPlan<S> {
+    states: Vec<S>,
+    tasks: Vec<(OctreeCreationPosition, usize)>,
+}
+
+impl<S> BuildPlan<S> {
+    // Samples the octant at pos until split_level, queueing the octants that still have to be built
+    fn plan<T: VoxelPayload>(&mut self, pos: OctreeCreationPosition, state: usize, split_level: u32, builder: &impl OctreeBuilder<S, T>, buffer: &mut UnmanagedByteBuffer) -> PlannedOctant<T> {
+        if pos.level() <= split_level {
+            self.tasks.push((pos, state));
+            return PlannedOctant::Task(self.tasks.len() - 1);
+        }
+
+        let state = match builder.get_octant(&pos, buffer, &self.states[state]) {
+            OctreeBuilderResult::Homogeneous(mat) => return PlannedOctant::Homogeneous(mat),
+            OctreeBuilderResult::Sparse => state,
+            OctreeBuilderResult::SamplingRequired(s) => {
+                self.states.push(s);
+                self.states.len() - 1
+            }
+        };
+
+        PlannedOctant::Split(Box::new(std::array::from_fn(|i| {
+            let child = unsafe { pos.child_unchecked(VoxelOctant::from_index(i)) };
+            self.plan(child, state, split_level, builder, buffer)
+        })))
+    }
+}
+
+impl<T: VoxelPayload + Send + Sync> VoxelOctree<T> {
+    // Creates a new voxel octree from an octree builder on the rayon thread pool. The top
+    // levels are sampled serially, the subtrees below them are built into local buffers in
+    // parallel and spliced together in order, so the result is identical to from_builder.
+    pub fn from_builder_parallel<S: Sync>(builder: &(impl OctreeBuilder<S, T> + Sync)) -> Self {
+        let tree_depth = builder.get_tree_depth();
+        assert!(tree_depth > 0 && tree_depth <= MAX_TREE_DEPTH, "The tree depth has to be between 1 and {}.", MAX_TREE_DEPTH);
+
+        let mut octree = Self::new(tree_depth, UnmanagedByteBuffer::new());
+        let mut buffer = octree.new_stream(0);
+
+        let split_level = tree_depth.saturating_sub(PARALLEL_LEVELS).max(1);
+        let mut plan = BuildPlan { states: vec![builder.default_state()], tasks: Vec::new() };
+        let top = plan.plan(OctreeCreationPosition::new(UVec3::ZERO, tree_depth, VoxelOctant::Z0Y0X0), 0, split_level, builder, &mut buffer);
+
+        let subtrees: Vec<(OctreeSlot<T>, UnmanagedByteBuffer)> = plan.tasks.par_iter()
+            .map(|(pos, state)| {
+                let mut local = UnmanagedByteBuffer::new();
+                let slot = octree.create_octree_data_linear(pos, builder, &mut local, &plan.states[*state]);
+                (slot, local)
+            })
+            .collect();
+
+        let top = octree.splice(top, &subtrees, &mut buffer);
+        octree.write_top(&mut buffer, top);
+        octree.buffer = buffer;
+        octree
+    }
+
+    // Writes the planned octant the way create_octree_data_linear would have, children first
+    fn splice(&self, octant: PlannedOctant<T>, subtrees: &[(OctreeSlot<T>, UnmanagedByteBuffer)], buffer: &mut UnmanagedByteBuffer) -> OctreeSlot<T> {
+        match octant {
+            PlannedOctant::Homogeneous(mat) => OctreeSlot::Homogeneous(self.default_record(mat)),
+            PlannedOctant::Task(task) => {
+                let (slot, local) = &subtrees[task];
+                let offset = buffer.count() as u32;
+                buffer.extend_from_slice(local.as_slice());
+                self.relocate(buffer, offset as usize);
+                match *slot {
+                    OctreeSlot::Node(node) => OctreeSlot::Node(node + offset),
+                    homogeneous => homogeneous,
+                }
+            }
+            PlannedOctant::Split(children) => {
+                let slots = (*children).map(|child| self.splice(child, subtrees, buffer));
+                match Self::merged_material(&slots) {
+                    Some(record) => OctreeSlot::Homogeneous(record),
+                    None => OctreeSlot::Node(self.write_node(buffer, &slots)),
+                }
+            }
+        }
+    }
+
+    // Moves the child pointers of the nodes from start to the end of buffer by start, they
+    // were written relative to a buffer of their own
+    fn relocate(&self, buffer: &mut UnmanagedByteBuffer, start: usize) {
+        let mut node = start;
+        while node < buffer.count() {
+            let mask = buffer.get(node) as u8;
+            let mut position = node + 1;
+            for i in 0..8 {
+                if mask & (1 << i) != 0 {
+                    let ptr = (buffer.get(position) as u32 | (buffer.get(position + 1) as u32) << 16) + start as u32;
+                    buffer.set(position, ptr as u16);
+                    buffer.set(position + 1, (ptr >> 16) as u16);
+                    position += 2;
+                } else {
+                    position += self.record_words();
+                }
+            }
+            node = position;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::density::*;
+    use crate::octree::tests::random_array;
+
+    #[test]
+    fn matches_from_builder_for_arrays() {
+        for size in 1..=16 {
+            for (seed, blobby) in [(size as u64, false), (size as u64 + 100, true)] {
+                let array = random_array([size, size, size], seed, 4, blobby);
+                let builder = Array3DOctreeBuilder::new(&array, 0);
+                let serial = VoxelOctree::from_builder(&builder);
+                let parallel = VoxelOctree::from_builder_parallel(&builder);
+                assert_eq!(parallel.buffer().as_slice(), serial.buffer().as_slice(), "size {}", size);
+                assert_eq!(parallel.verify_packed(), Ok(()));
+            }
+        }
+    }
+
+    #[test]
+    fn matches_from_builder_with_sampling_states() {
+        for seed in 0..3 {
+            let settings = DensitySettings { seed, base_height: 32.0, amplitude: 12.0, frequency: 1.0 / 24.0, cave_frequency: 1.0 / 16.0, cave_radius: 0.15, ..Default::default() };
+            let builder = DensityCaveBuilder::new(6, settings);
+            let serial = VoxelOctree::from_builder(&builder);
+            let parallel = VoxelOctree::from_builder_parallel(&builder);
+            assert_eq!(parallel.buffer().as_slice(), serial.buffer().as_slice(), "seed {}", seed);
+            assert_eq!(parallel.verify_packed(), Ok(()));
+        }
+    }
+}
\ No newline at end of file
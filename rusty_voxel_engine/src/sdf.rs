@@ -0,0 +1,199 @@
+// sdf.rs
+use crate::builder::*;
+use crate::octree::*;
+use crate::types::*;
+
+use glam::{DVec2, DVec3};
+
+// A shape described by its signed distance in voxels, negative inside. Every primitive is an
+// exact distance and every operation keeps the result from growing faster than the distance,
+// so the value at a point bounds how far away the surface is.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Sdf {
+    Sphere { center: DVec3, radius: f64 },
+    Cuboid { center: DVec3, half_extents: DVec3 },
+    // A cylinder between a and b with rounded ends
+    Capsule { a: DVec3, b: DVec3, radius: f64 },
+    // A ring around the y axis through center
+    Torus { center: DVec3, major_radius: f64, minor_radius: f64 },
+    Union(Box<Sdf>, Box<Sdf>),
+    Intersect(Box<Sdf>, Box<Sdf>),
+    // The first shape with the second one carved out
+    Subtract(Box<Sdf>, Box<Sdf>),
+    // A union blending the shapes over a distance of k
+    SmoothUnion(Box<Sdf>, Box<Sdf>, f64),
+    Translate(Box<Sdf>, DVec3),
+}
+
+impl Sdf {
+    pub fn sphere(center: DVec3, radius: f64) -> Self {
+        Sdf::Sphere { center, radius }
+    }
+
+    pub fn cuboid(center: DVec3, half_extents: DVec3) -> Self {
+        Sdf::Cuboid { center, half_extents }
+    }
+
+    pub fn capsule(a: DVec3, b: DVec3, radius: f64) -> Self {
+        Sdf::Capsule { a, b, radius }
+    }
+
+    pub fn torus(center: DVec3, major_radius: f64, minor_radius: f64) -> Self {
+        Sdf::Torus { center, major_radius, minor_radius }
+    }
+
+    pub fn union(self, other: Sdf) -> Self {
+        Sdf::Union(Box::new(self), Box::new(other))
+    }
+
+    pub fn intersect(self, other: Sdf) -> Self {
+        Sdf::Intersect(Box::new(self), Box::new(other))
+    }
+
+    pub fn subtract(self, other: Sdf) -> Self {
+        Sdf::Subtract(Box::new(self), Box::new(other))
+    }
+
+    pub fn smooth_union(self, other: Sdf, k: f64) -> Self {
+        assert!(k > 0.0, "The blend distance has to be positive.");
+        Sdf::SmoothUnion(Box::new(self), Box::new(other), k)
+    }
+
+    pub fn translate(self, offset: DVec3) -> Self {
+        Sdf::Translate(Box::new(self), offset)
+    }
+
+    pub fn distance(&self, p: DVec3) -> f64 {
+        match self {
+            Sdf::Sphere { center, radius } => (p - *center).length() - radius,
+            Sdf::Cuboid { center, half_extents } => {
+                let q = (p - *center).abs() - *half_extents;
+                q.max(DVec3::ZERO).length() + q.max_element().min(0.0)
+            }
+            Sdf::Capsule { a, b, radius } => {
+                let (pa, ba) = (p - *a, *b - *a);
+                let h = if ba == DVec3::ZERO { 0.0 } else { (pa.dot(ba) / ba.dot(ba)).clamp(0.0, 1.0) };
+                (pa - ba * h).length() - radius
+            }
+            Sdf::Torus { center, major_radius, minor_radius } => {
+                let q = p - *center;
+                DVec2::new(DVec2::new(q.x, q.z).length() - major_radius, q.y).length() - minor_radius
+            }
+            Sdf::Union(a, b) => a.distance(p).min(b.distance(p)),
+            Sdf::Intersect(a, b) => a.distance(p).max(b.distance(p)),
+            Sdf::Subtract(a, b) => a.distance(p).max(-b.distance(p)),
+            Sdf::SmoothUnion(a, b, k) => {
+                let (a, b) = (a.distance(p), b.distance(p));
+                let h = (k - (a - b).abs()).max(0.0) / k;
+                a.min(b) - h * h * k / 4.0
+            }
+            Sdf::Translate(shape, offset) => shape.distance(p - *offset),
+        }
+    }
+}
+
+// Voxelizes an Sdf, a voxel is solid when the distance at its center is at most 0. An octant
+// whose center is farther from the surface than from its outermost voxel center is
+// homogeneous, so only octants near the surface get sampled.
+pub struct SdfOctreeBuilder {
+    sdf: Sdf,
+    tree_depth: u32,
+    material: u16,
+    empty: u16,
+}
+
+impl SdfOctreeBuilder {
+    pub fn new(sdf: Sdf, tree_depth: u32, material: u16, empty: u16) -> Self {
+        assert!(tree_depth > 0 && tree_depth <= MAX_TREE_DEPTH, "The tree depth has to be between 1 and {}.", MAX_TREE_DEPTH);
+        Self { sdf, tree_depth, material, empty }
+    }
+
+    pub fn sdf(&self) -> &Sdf {
+        &self.sdf
+    }
+
+    pub fn is_solid(&self, pos: glam::UVec3) -> bool {
+        self.sdf.distance(pos.as_dvec3() + DVec3::splat(0.5)) <= 0.0
+    }
+
+    fn material(&self, pos: glam::UVec3) -> u16 {
+        if self.is_solid(pos) { self.material } else { self.empty }
+    }
+}
+
+impl OctreeBuilder<()> for SdfOctreeBuilder {
+    fn default_state(&self) {}
+
+    fn get_tree_depth(&self) -> u32 {
+        self.tree_depth
+    }
+
+    fn get_octant(&self, pos: &OctreeCreationPosition, _buffer: &mut UnmanagedByteBuffer, _state: &()) -> OctreeBuilderResult<()> {
+        if pos.level() == 0 {
+            return OctreeBuilderResult::Homogeneous(self.material(pos.position()));
+        }
+
+        let half = pos.size() as f64 / 2.0;
+        let distance = self.sdf.distance(pos.position().as_dvec3() + DVec3::splat(half));
+        // Distance from the center of the octant to the center of a corner voxel
+        let reach = (half - 0.5) * 3f64.sqrt();
+
+        if distance > reach {
+            OctreeBuilderResult::Homogeneous(self.empty)
+        } else if distance + reach <= 0.0 {
+            OctreeBuilderResult::Homogeneous(self.material)
+        } else {
+            OctreeBuilderResult::Sparse
+        }
+    }
+
+    fn get_block(&self, pos: &OctreeCreationPosition, _buffer: &mut UnmanagedByteBuffer, _state: &()) -> [u16; 8] {
+        VoxelOctant::ALL.map(|octant| self.material(pos.position() + octant.to_offset()))
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use glam::UVec3;
+
+    fn assert_pruned_build_matches_is_solid(sdf: Sdf) {
+        let builder = SdfOctreeBuilder::new(sdf, 5, 1, 0);
+        let octree = VoxelOctree::from_builder(&builder);
+        let mut solid = 0;
+        for x in 0..32 {
+            for y in 0..32 {
+                for z in 0..32 {
+                    let pos = UVec3::new(x, y, z);
+                    let is_solid = builder.is_solid(pos);
+                    assert_eq!(octree.get(pos) == 1, is_solid, "{:?} at {}", builder.sdf(), pos);
+                    solid += is_solid as usize;
+                }
+            }
+        }
+        // The surface runs through the tree and whole octants were skipped
+        assert!(solid > 0 && solid < 32 * 32 * 32, "{:?}", builder.sdf());
+        assert!(octree.iter_regions().count() < 32 * 32 * 32 / 2, "{:?}", builder.sdf());
+    }
+
+    #[test]
+    fn pruned_build_matches_is_solid() {
+        let center = DVec3::splat(16.0);
+        let shapes = [
+            Sdf::sphere(center, 11.3),
+            Sdf::sphere(DVec3::new(3.0, 29.5, 8.2), 9.0),
+            Sdf::cuboid(center, DVec3::new(10.5, 4.2, 7.0)),
+            Sdf::torus(center, 9.5, 3.7),
+            Sdf::capsule(DVec3::new(4.0, 5.5, 6.0), DVec3::new(27.0, 20.0, 25.5), 4.4),
+            Sdf::capsule(center, center, 6.5),
+            Sdf::sphere(DVec3::new(10.0, 16.0, 16.0), 7.0).smooth_union(Sdf::sphere(DVec3::new(22.0, 16.0, 16.0), 7.0), 6.0),
+            Sdf::cuboid(DVec3::new(8.0, 8.0, 8.0), DVec3::splat(5.0)).smooth_union(Sdf::torus(center, 10.0, 2.5), 4.0),
+            Sdf::cuboid(center, DVec3::splat(12.0)).subtract(Sdf::sphere(center, 14.5)),
+            Sdf::sphere(center, 13.0).subtract(Sdf::capsule(DVec3::new(16.0, 0.0, 16.0), DVec3::new(16.0, 32.0, 16.0), 4.0)),
+            Sdf::sphere(center, 12.0).intersect(Sdf::cuboid(center, DVec3::new(20.0, 6.0, 20.0))),
+            Sdf::torus(DVec3::ZERO, 8.0, 3.0).union(Sdf::sphere(DVec3::ZERO, 5.0)).translate(DVec3::new(14.5, 9.0, 17.0)),
+        ];
+        for sdf in shapes {
+            assert_pruned_build_matches_is_solid(sdf);
+        }
+    }
+}
\ No newline at end of file
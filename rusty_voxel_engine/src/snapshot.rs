@@ -0,0 +1,121 @@
+// snapshot.rs
+use crate::octree::*;
+use crate::types::*;
+
+use std::sync::Arc;
+
+// An immutable version of a VoxelOctree. It shares every node with the tree it was taken
+// from, so it is cheap to take, clone and keep around. Reads go through Deref.
+#[derive(Clone)]
+pub struct VoxelOctreeSnapshot<T: VoxelPayload = u16> {
+    octree: VoxelOctree<T>,
+}
+
+impl<T: VoxelPayload> VoxelOctreeSnapshot<T> {
+    // Creates an editable tree starting out from this snapshot, sharing its nodes
+    pub fn to_octree(&self) -> VoxelOctree<T> {
+        let mut octree = self.octree.clone();
+        octree.thaw_top();
+        octree
+    }
+}
+
+impl<T: VoxelPayload> std::ops::Deref for VoxelOctreeSnapshot<T> {
+    type Target = VoxelOctree<T>;
+
+    fn deref(&self) -> &VoxelOctree<T> {
+        &self.octree
+    }
+}
+
+impl<T: VoxelPayload> VoxelOctree<T> {
+    // Takes an immutable snapshot of the tree. Nothing gets copied, the nodes written so far
+    // are frozen and shared, later edits copy the path to every node they touch.
+    pub fn snapshot(&mut self) -> VoxelOctreeSnapshot<T> {
+        self.freeze();
+        let snapshot = VoxelOctreeSnapshot { octree: self.clone() };
+        self.thaw_top();
+        snapshot
+    }
+
+    // Resets the tree to a snapshot, e.g. to undo the edits made since it was taken
+    pub fn restore(&mut self, snapshot: &VoxelOctreeSnapshot<T>) {
+        assert!(self.tree_depth == snapshot.tree_depth, "The snapshot has a different tree depth.");
+        *self = snapshot.to_octree();
+    }
+
+    // Moves the live buffer into a shared segment, the buffer is empty afterwards
+    fn freeze(&mut self) {
+        if self.buffer.is_empty() {
+            return;
+        }
+
+        let words = Arc::new(std::mem::take(&mut self.buffer));
+        let start = self.frozen_len;
+        self.frozen_len += words.count();
+        self.frozen.push((start, words));
+    }
+
+    // Copies the top slot into the live buffer so it can be changed again
+    fn thaw_top(&mut self) {
+        if self.top >= self.frozen_len {
+            return;
+        }
+
+        let len = self.top_len();
+        let mut top = [0; MAX_RECORD_WORDS + 1];
+        for (i, word) in top[..len].iter_mut().enumerate() {
+            *word = self.word(self.top + i);
+        }
+        self.top = self.stream_len();
+        self.buffer.extend_from_slice(&top[..len]);
+        self.garbage += len;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use glam::UVec3;
+
+    #[test]
+    fn snapshot_keeps_old_values_and_restores() {
+        let mut octree = VoxelOctree::filled(4, 0u16);
+        for i in 0..16 {
+            octree.set(UVec3::new(i, i / 2, 15 - i), i as u16 % 3 + 1);
+        }
+        let before: Vec<u16> = (0..16).map(|i| octree.get(UVec3::new(i, i / 2, 15 - i))).collect();
+
+        let snapshot = octree.snapshot();
+        assert_eq!(octree.verify(), Ok(()));
+        assert_eq!(snapshot.verify(), Ok(()));
+
+        octree.fill(UVec3::ZERO, UVec3::new(7, 7, 7), 9);
+        octree.set(UVec3::new(15, 7, 0), 4);
+        assert_eq!(octree.verify(), Ok(()));
+        assert_eq!(octree.get(UVec3::new(3, 1, 12)), before[3]);
+        assert_eq!(octree.get(UVec3::new(15, 7, 0)), 4);
+        assert_eq!(octree.get(UVec3::new(2, 3, 4)), 9);
+
+        // The snapshot still reads the values from before the edits
+        for i in 0..16 {
+            assert_eq!(snapshot.get(UVec3::new(i, i / 2, 15 - i)), before[i as usize]);
+        }
+        assert_eq!(snapshot.get(UVec3::new(2, 3, 4)), 0);
+        assert_eq!(snapshot.verify(), Ok(()));
+
+        octree.restore(&snapshot);
+        assert_eq!(octree.verify(), Ok(()));
+        for i in 0..16 {
+            assert_eq!(octree.get(UVec3::new(i, i / 2, 15 - i)), before[i as usize]);
+        }
+        assert_eq!(octree.get(UVec3::new(2, 3, 4)), 0);
+
+        // Edits after restoring don't reach the snapshot either
+        octree.set(UVec3::new(0, 0, 15), 7);
+        assert_eq!(octree.verify(), Ok(()));
+        assert_eq!(snapshot.get(UVec3::new(0, 0, 15)), before[0]);
+        octree.compact();
+        assert_eq!(octree.verify_packed(), Ok(()));
+    }
+}
\ No newline at end of file
@@ -0,0 +1,175 @@
+// transform.rs
+use crate::octree::*;
+use crate::types::*;
+
+use glam::{IVec3, UVec3};
+
+impl<T: VoxelPayload> VoxelOctree<T> {
+    // Rotates the tree by 90 degrees around axis following the right hand rule
+    pub fn rotate90(&self, axis: Axis) -> VoxelOctree<T> {
+        self.permuted(|p| match axis {
+            Axis::X => UVec3::new(p.x, 1 - p.z, p.y),
+            Axis::Y => UVec3::new(p.z, p.y, 1 - p.x),
+            Axis::Z => UVec3::new(1 - p.y, p.x, p.z),
+        })
+    }
+
+    // Mirrors the tree along axis
+    pub fn mirror(&self, axis: Axis) -> VoxelOctree<T> {
+        self.permuted(|p| match axis {
+            Axis::X => UVec3::new(1 - p.x, p.y, p.z),
+            Axis::Y => UVec3::new(p.x, 1 - p.y, p.z),
+            Axis::Z => UVec3::new(p.x, p.y, 1 - p.z),
+        })
+    }
+
+    // Moves the content of the tree by offset octants of edge length 1 << level.
+    // Voxels moved out of the tree are dropped and the uncovered space is filled with fill
+    // and the default layer values.
+    pub fn translate_by_octants(&self, offset: IVec3, level: u32, fill: T) -> VoxelOctree<T> {
+        assert!(level <= self.tree_depth, "The octant level can't be above the tree depth ({}).", self.tree_depth);
+
+        let shift = offset.to_array().map(|o| (o as i64) << level);
+        let mut buffer = self.new_stream(0);
+
+        let top = self.translate_slot(UVec3::ZERO, self.tree_depth, shift, self.default_record(fill), &mut buffer);
+        self.write_top(&mut buffer, top);
+
+        self.with_layout(self.tree_depth, buffer)
+    }
+
+    // Rebuilds the tree with every child moved to the octant map returns for its offset.
+    // The same permutation applies at every level since it only flips and swaps axes.
+    fn permuted(&self, map: impl Fn(UVec3) -> UVec3) -> VoxelOctree<T> {
+        // The source octant of every child of the new tree
+        let mut source = [0; 8];
+        for octant in VoxelOctant::iter_all() {
+            source[VoxelOctant::from_offset(map(octant.to_offset())).index()] = octant.index();
+        }
+
+        let mut buffer = self.new_stream(0);
+
+        let top = self.permute_slot(self.top_slot(), &source, &mut buffer);
+        self.write_top(&mut buffer, top);
+
+        self.with_layout(self.tree_depth, buffer)
+    }
+
+    fn permute_slot(&self, slot: OctreeSlot<T>, source: &[usize; 8], buffer: &mut UnmanagedByteBuffer) -> OctreeSlot<T> {
+        match slot {
+            OctreeSlot::Homogeneous(_) => slot,
+            OctreeSlot::Node(node) => {
+                let children = self.children(node);
+                let mut permuted = [OctreeSlot::Node(0); 8];
+                for (i, child) in permuted.iter_mut().enumerate() {
+                    *child = self.permute_slot(children[source[i]], source, buffer);
+                }
+                OctreeSlot::Node(self.write_node(buffer, &permuted))
+            }
+        }
+    }
+
+    // Builds the octant at pos of the translated tree. Octants that line up with an octant of
+    // the source are copied as a whole, the others are split until they do.
+    fn translate_slot(&self, pos: UVec3, level: u32, shift: [i64; 3], fill: VoxelRecord<T>, buffer: &mut UnmanagedByteBuffer) -> OctreeSlot<T> {
+        let size = 1i64 << level;
+        let tree_size = self.size() as i64;
+        let source = [0, 1, 2].map(|i| pos[i] as i64 - shift[i]);
+
+        if source.iter().any(|s| *s + size <= 0 || *s >= tree_size) {
+            return OctreeSlot::Homogeneous(fill);
+        }
+        if source.iter().all(|s| *s >= 0 && *s + size <= tree_size && *s % size == 0) {
+            let slot = self.slot_at(UVec3::new(source[0] as u32, source[1] as u32, source[2] as u32), level);
+            return self.copy_slot(slot, buffer);
+        }
+
+        let mut children = [OctreeSlot::Node(0); 8];
+        for (i, child) in children.iter_mut().enumerate() {
+            let child_pos = pos + (VoxelOctant::from_index(i).to_offset() << (level - 1));
+            *child = self.translate_slot(child_pos, level - 1, shift, fill, buffer);
+        }
+
+        match Self::merged_material(&children) {
+            Some(record) => OctreeSlot::Homogeneous(record),
+            None => OctreeSlot::Node(self.write_node(buffer, &children)),
+        }
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::*;
+    use crate::octree::tests::{assert_matches_array, random_array};
+
+    // Moves every voxel of array to where map sends it, voxels sent outside are dropped
+    fn brute_force(array: &multiarray::Array3D<u16>, fill: u16, map: impl Fn(IVec3) -> IVec3) -> multiarray::Array3D<u16> {
+        let n = array.extents()[0];
+        let mut moved = multiarray::Array3D::new([n; 3], fill);
+        for x in 0..n {
+            for y in 0..n {
+                for z in 0..n {
+                    let to = map(IVec3::new(x as i32, y as i32, z as i32));
+                    if to.cmpge(IVec3::ZERO).all() && to.cmplt(IVec3::splat(n as i32)).all() {
+                        moved[[to.x as usize, to.y as usize, to.z as usize]] = array[[x, y, z]];
+                    }
+                }
+            }
+        }
+        moved
+    }
+
+    fn arrays() -> Vec<multiarray::Array3D<u16>> {
+        vec![random_array([16, 16, 16], 4, 3, true), random_array([16, 16, 16], 13, 4, false), random_array([4, 4, 4], 2, 3, false)]
+    }
+
+    #[test]
+    fn rotate90_matches_brute_force() {
+        for array in arrays() {
+            let octree = VoxelOctree::from_builder(&Array3DOctreeBuilder::new(&array, 0));
+            let n = octree.size() as i32 - 1;
+            for axis in [Axis::X, Axis::Y, Axis::Z] {
+                let rotated = octree.rotate90(axis);
+                assert_eq!(rotated.verify_packed(), Ok(()));
+                assert_matches_array(&rotated, &brute_force(&array, 0, |p| match axis {
+                    Axis::X => IVec3::new(p.x, n - p.z, p.y),
+                    Axis::Y => IVec3::new(p.z, p.y, n - p.x),
+                    Axis::Z => IVec3::new(n - p.y, p.x, p.z),
+                }), 0);
+
+                // Four quarter turns make a full turn
+                let full = (0..4).fold(octree.clone(), |octree, _| octree.rotate90(axis));
+                assert_eq!(full.buffer().as_slice(), octree.buffer().as_slice());
+            }
+        }
+    }
+
+    #[test]
+    fn mirror_matches_brute_force() {
+        for array in arrays() {
+            let octree = VoxelOctree::from_builder(&Array3DOctreeBuilder::new(&array, 0));
+            let n = octree.size() as i32 - 1;
+            for axis in [Axis::X, Axis::Y, Axis::Z] {
+                let mirrored = octree.mirror(axis);
+                assert_eq!(mirrored.verify_packed(), Ok(()));
+                assert_matches_array(&mirrored, &brute_force(&array, 0, |mut p| {
+                    p[axis as usize] = n - p[axis as usize];
+                    p
+                }), 0);
+                assert_eq!(mirrored.mirror(axis).buffer().as_slice(), octree.buffer().as_slice());
+            }
+        }
+    }
+
+    #[test]
+    fn translate_by_octants_matches_brute_force() {
+        for array in arrays() {
+            let octree = VoxelOctree::from_builder(&Array3DOctreeBuilder::new(&array, 0));
+            for (offset, level) in [(IVec3::new(1, 0, 0), 0), (IVec3::new(-3, 2, 5), 0), (IVec3::new(1, -1, 1), 1), (IVec3::new(0, 1, -2), 2), (IVec3::new(1, 0, 0), octree.tree_depth()), (IVec3::ZERO, 1)] {
+                let moved = octree.translate_by_octants(offset, level, 7);
+                assert_eq!(moved.verify_packed(), Ok(()));
+                assert_matches_array(&moved, &brute_force(&array, 7, |p| p + offset * (1 << level)), 7);
+            }
+        }
+    }
+}
\ No newline at end of file
@@ -5,22 +5,175 @@ use bitflags::*;
 
 #[derive(Clone)]
 pub struct Voxel {
-    pub material: u16, 
+    pub material: u16,
     pub leaf: bool
 }
 
+/* // needs a Bounds type
 pub struct OctreeNode {
-    pub bounds: Bounds, 
+    pub bounds: Bounds,
     pub children: [Option<Box<OctreeNode>>; 8],
     pub voxels: Vec<Voxel>
 }
+*/
+
+// A voxel octree packed into a linear node stream, storing a VoxelPayload and one word per
+// registered attribute layer for every voxel.
+//
+// The stream starts with the top slot: a header word holding NodeFlags in the high byte,
+// followed either by the record of the whole tree (NodeFlags::HOMOGENEOUS) or by a two
+// word pointer to the root node, padded with zero words to the longer of the two. A record
+// is the payload followed by the value of every layer.
+//
+// A node is a header word whose low byte is the child mask, followed by one slot per child
+// in VoxelOctant order. A child with its bit set in the mask is subdivided and its slot is a
+// two word pointer (low word first) to its node, otherwise the slot holds the record
+// filling the whole child. Nodes at level 1 have voxels as children, so their mask is
+// always empty. Children are written before their parents.
+//
+// Edits patch nodes in place while their child mask stays the same and append a new copy
+// otherwise, garbage counts the words of nodes that are no longer referenced.
+//
+// Taking a snapshot freezes the words written so far into a segment shared with the
+// snapshot. Frozen nodes are never patched, edits copy the path to them into the buffer,
+// which holds the words from frozen_len on. top is the position of the live top slot.
+#[derive(Clone)]
+pub struct VoxelOctree<T: VoxelPayload = u16> {
+    pub(crate) tree_depth: u32,
+    pub(crate) buffer: UnmanagedByteBuffer,
+    pub(crate) garbage: usize,
+    pub(crate) frozen: Vec<(usize, std::sync::Arc<UnmanagedByteBuffer>)>,
+    pub(crate) frozen_len: usize,
+    pub(crate) top: usize,
+    pub(crate) layers: Vec<VoxelLayer>,
+    pub(crate) payload: std::marker::PhantomData<T>,
+}
+
+// Most attribute layers a VoxelOctree can have
+pub const MAX_LAYERS: usize = 8;
+
+// A per voxel attribute stored next to the payload, like a light level or damage
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VoxelLayer {
+    pub name: String,
+    pub default: u16,
+}
+
+// Index of a layer in the order it was registered on a VoxelOctree
+pub type VoxelLayerId = usize;
+
+// Everything stored for a homogeneous octant: its payload and the value of every layer.
+// Values of unregistered layers are always 0.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VoxelRecord<T = u16> {
+    pub material: T,
+    pub layers: [u16; MAX_LAYERS],
+}
+
+impl<T> VoxelRecord<T> {
+    pub fn new(material: T) -> Self {
+        Self { material, layers: [0; MAX_LAYERS] }
+    }
+}
+
+// Most words a VoxelPayload can take
+pub const MAX_PAYLOAD_WORDS: usize = 8;
+
+// A value stored per voxel of a VoxelOctree: materials, light levels, densities or small
+// structs. It is packed into WORDS words of the node stream, at most MAX_PAYLOAD_WORDS.
+pub trait VoxelPayload: Copy + Eq {
+    const WORDS: usize;
+
+    fn to_words(self, words: &mut [u16]);
+    fn from_words(words: &[u16]) -> Self;
+}
+
+macro_rules! impl_voxel_payload {
+    ($($t:ty),*) => {
+        $(
+            impl VoxelPayload for $t {
+                const WORDS: usize = (std::mem::size_of::<$t>() + 1) / 2;
+
+                fn to_words(self, words: &mut [u16]) {
+                    let bytes = self.to_le_bytes();
+                    for (i, word) in words.iter_mut().enumerate() {
+                        *word = bytes[2 * i] as u16 | (bytes.get(2 * i + 1).copied().unwrap_or(0) as u16) << 8;
+                    }
+                }
+
+                fn from_words(words: &[u16]) -> Self {
+                    let mut bytes = [0u8; std::mem::size_of::<$t>()];
+                    for (i, byte) in bytes.iter_mut().enumerate() {
+                        *byte = (words[i / 2] >> (8 * (i % 2))) as u8;
+                    }
+                    Self::from_le_bytes(bytes)
+                }
+            }
+        )*
+    };
+}
+
+impl_voxel_payload!(u8, i8, u16, i16, u32, i32, u64, i64);
+
+impl VoxelPayload for bool {
+    const WORDS: usize = 1;
+
+    fn to_words(self, words: &mut [u16]) {
+        words[0] = self as u16;
+    }
+
+    fn from_words(words: &[u16]) -> Self {
+        words[0] != 0
+    }
+}
+
+// A homogeneous octant of a VoxelOctree, level is the log2 of its edge length
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OctreeRegion<T = u16> {
+    pub min: UVec3,
+    pub level: u32,
+    pub material: T,
+}
 
-pub struct VoxelOctree {
-    tree_depth: u32,
-    buffer: UnmanagedByteBuffer,
+// A rectangle of voxels on a face of an octant, [x, u) by [y, v). The face spans y and z for
+// the x directions, x and z for y and x and y for z.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FaceExtent {
+    pub x: u8,
+    pub y: u8,
+    pub u: u8,
+    pub v: u8,
 }
+
+impl FaceExtent {
+    pub fn new_with_points(x: u8, y: u8, u: u8, v: u8) -> Self {
+        Self { x, y, u, v }
+    }
+
+    pub fn width(&self) -> u8 {
+        self.u - self.x
+    }
+
+    pub fn height(&self) -> u8 {
+        self.v - self.y
+    }
+
+    // The smallest rectangle covering both
+    pub fn union(self, other: FaceExtent) -> Self {
+        Self::new_with_points(self.x.min(other.x), self.y.min(other.y), self.u.max(other.u), self.v.max(other.v))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Axis {
+    X,
+    Y,
+    Z,
+}
+
 // A voxel octant refers to an octant of an octree that is used to represent a voxel grid.
 bitflags! {
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
     pub struct VoxelOctant: u8 {
         const Z0Y0X0 = 0b000;
         const Z0Y1X0 = 0b001;
@@ -32,12 +185,219 @@ bitflags! {
         const Z1Y1X1 = 0b111;
     }
 }
+
+impl From<VoxelOctant> for UVec3 {
+    fn from(octant: VoxelOctant) -> Self {
+        octant.to_offset()
+    }
+}
+
+// Where the neighbour of an octant lies across one of its faces
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OctantNeighbor {
+    // The neighbour is another child of the same parent
+    Sibling(VoxelOctant),
+    // The neighbour is a child of the parent's neighbour in the same direction, the octant
+    // it has in that parent
+    AcrossParent(VoxelOctant),
+}
+
+impl VoxelOctant {
+    // Every octant in VoxelOctant order. VoxelOctant::all() is the set of all bits.
+    pub const ALL: [VoxelOctant; 8] = [
+        Self::Z0Y0X0, Self::Z0Y1X0, Self::Z1Y0X0, Self::Z1Y1X0,
+        Self::Z0Y0X1, Self::Z0Y1X1, Self::Z1Y0X1, Self::Z1Y1X1,
+    ];
+
+    pub fn from_index(index: usize) -> Self {
+        assert!(index < 8, "An octant index has to be below 8.");
+        Self::from_bits_retain(index as u8)
+    }
+
+    // Position of the child slot in a node
+    pub fn index(self) -> usize {
+        self.bits() as usize
+    }
+
+    // Iterates over every octant in VoxelOctant order
+    pub fn iter_all() -> impl Iterator<Item = VoxelOctant> {
+        Self::ALL.into_iter()
+    }
+
+    // Offset of the octant inside of its parent in units of the octant size, 0 or 1 per axis
+    pub fn to_offset(self) -> UVec3 {
+        let bits = self.bits() as u32;
+        UVec3::new((bits >> 2) & 1, bits & 1, (bits >> 1) & 1)
+    }
+
+    // Inverse of to_offset, only the lowest bit of every axis is used
+    pub fn from_offset(offset: UVec3) -> Self {
+        let bit = offset & UVec3::ONE;
+        Self::from_bits_retain(((bit.x << 2) | (bit.z << 1) | bit.y) as u8)
+    }
+
+    // The octant of the child at level containing pos
+    pub fn containing(pos: UVec3, level: u32) -> Self {
+        Self::from_offset(pos >> level)
+    }
+
+    pub fn axis_bit(axis: Axis) -> Self {
+        match axis {
+            Axis::X => Self::Z0Y0X1,
+            Axis::Y => Self::Z0Y1X0,
+            Axis::Z => Self::Z1Y0X0,
+        }
+    }
+
+    // Whether the octant sits on the positive side of its parent along axis
+    pub fn is_positive(self, axis: Axis) -> bool {
+        self.contains(Self::axis_bit(axis))
+    }
+
+    // The octant mirrored along axis
+    pub fn flipped(self, axis: Axis) -> Self {
+        self ^ Self::axis_bit(axis)
+    }
+
+    // The octant diagonally opposite in the parent
+    pub fn opposite(self) -> Self {
+        self ^ Self::Z1Y1X1
+    }
+
+    // The octant next to this one in direction. Moving towards the side of the parent the
+    // octant already touches leaves the parent, either way the neighbour is the flipped octant.
+    pub fn face_neighbor(self, direction: Direction) -> OctantNeighbor {
+        let neighbor = self.flipped(direction.axis());
+        if self.is_positive(direction.axis()) == direction.is_positive() {
+            OctantNeighbor::AcrossParent(neighbor)
+        } else {
+            OctantNeighbor::Sibling(neighbor)
+        }
+    }
+
+    // Whether the octant touches the face of its parent in direction
+    pub fn touches_face(self, direction: Direction) -> bool {
+        self.is_positive(direction.axis()) == direction.is_positive()
+    }
+
+    // The four octants touching the face of their parent in direction, in VoxelOctant order
+    pub fn on_face(direction: Direction) -> [VoxelOctant; 4] {
+        let mut octants = [Self::Z0Y0X0; 4];
+        for (slot, octant) in octants.iter_mut().zip(Self::iter_all().filter(|octant| octant.touches_face(direction))) {
+            *slot = octant;
+        }
+        octants
+    }
+}
+
+// The six faces of a cube, also used as a set of faces. The bit index of a single direction
+// is the face index used by the renderer: -x, +x, -y, +y, -z, +z.
+bitflags! {
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+    pub struct Direction: u8 {
+        const NONE = 0;
+        const LEFT = 0b1;
+        const RIGHT = 0b10;
+        const DOWN = 0b100;
+        const UP = 0b1000;
+        const BACK = 0b10000;
+        const FRONT = 0b100000;
+    }
+}
+
+impl Direction {
+    // Every single direction in face index order
+    pub const FACES: [Direction; 6] = [Self::LEFT, Self::RIGHT, Self::DOWN, Self::UP, Self::BACK, Self::FRONT];
+
+    pub fn from_face_index(index: usize) -> Self {
+        assert!(index < 6, "A face index has to be below 6.");
+        Self::from_bits_retain(1 << index)
+    }
+
+    pub fn face_index(self) -> usize {
+        debug_assert!(self.bits().count_ones() == 1, "Only a single direction has a face index.");
+        self.bits().trailing_zeros() as usize
+    }
+
+    // Iterates over the single directions in the set
+    pub fn faces(self) -> impl Iterator<Item = Direction> {
+        Self::FACES.into_iter().filter(move |face| self.contains(*face))
+    }
+
+    pub fn axis(self) -> Axis {
+        match self.face_index() >> 1 {
+            0 => Axis::X,
+            1 => Axis::Y,
+            _ => Axis::Z,
+        }
+    }
+
+    pub fn is_positive(self) -> bool {
+        self.face_index() & 1 == 1
+    }
+
+    pub fn opposite(self) -> Self {
+        Self::from_face_index(self.face_index() ^ 1)
+    }
+
+    // Unit step in direction
+    pub fn to_ivec3(self) -> IVec3 {
+        let mut step = IVec3::ZERO;
+        step[self.axis() as usize] = if self.is_positive() { 1 } else { -1 };
+        step
+    }
+}
+
+// Reasons for VoxelOctree::verify to reject a node stream. node is the position of the
+// offending node and path the octants leading to it from the root.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VoxelOctreeVerificationError {
+    // A child pointer of node points outside of the node stream
+    ChildPointerOutOfRange { node: usize, path: Vec<VoxelOctant>, pointer: u32 },
+    // All eight children are the same homogeneous material and should have been merged
+    NonCanonicalNode { node: usize, path: Vec<VoxelOctant> },
+    // Unknown flags, or a child mask that doesn't fit the level of the node
+    BadFlags { node: usize, path: Vec<VoxelOctant>, header: u16 },
+    // The node runs past the end of the buffer
+    BufferOverrun { node: usize, path: Vec<VoxelOctant> },
+    // The node shares words with another node
+    OverlappingNode { node: usize, path: Vec<VoxelOctant> },
+    // The words from this position on aren't referenced by the tree
+    NonPackedOctree(usize),
+}
+
+impl std::fmt::Display for VoxelOctreeVerificationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::ChildPointerOutOfRange { node, path, pointer } => write!(f, "node {} at {:?} points outside of the buffer ({})", node, path, pointer),
+            Self::NonCanonicalNode { node, path } => write!(f, "node {} at {:?} has eight identical children", node, path),
+            Self::BadFlags { node, path, header } => write!(f, "node {} at {:?} has an invalid header ({:#06x})", node, path, header),
+            Self::BufferOverrun { node, path } => write!(f, "node {} at {:?} runs past the end of the buffer", node, path),
+            Self::OverlappingNode { node, path } => write!(f, "node {} at {:?} overlaps another node", node, path),
+            Self::NonPackedOctree(position) => write!(f, "unused words from position {}", position),
+        }
+    }
+}
+
+impl std::error::Error for VoxelOctreeVerificationError {}
+
+// Flags stored in the high byte of a node header word
+bitflags! {
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+    pub struct NodeFlags: u8 {
+        // Only valid in the top slot, the whole tree is a single material
+        const HOMOGENEOUS = 0b1;
+        // Only valid in the top slot, identical subtrees are stored once and shared
+        const DAG = 0b10;
+    }
+}
+
 /*
 pub struct Ray {
     pub origin: Point3,
-    pub direction: Vector3,  
+    pub direction: Vector3,
     pub t: f32,
-    pub object_id: Option<ObjectId>,  
+    pub object_id: Option<ObjectId>,
     pub ray_id: u32
 }
 */
@@ -50,13 +410,478 @@ pub struct Vertex {
 }
 implement_vertex!(Vertex, position, size, ambient, normal);
 
-pub struct UnmanagedByteBuffer {
-    pub tree_data: *mut u16,
-    pub capacity: usize
+// Order in which an Array3D2P stores its elements
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Array3DLayout {
+    // x first, then y, then z
+    Linear,
+    // Z-order, the bits of the coordinates interleaved in VoxelOctant order. Every aligned
+    // 2^n cube is stored contiguously and its eight children follow each other.
+    Morton,
+}
+
+// A cubic 3D array with a power of two edge length
+pub struct Array3D2P<T> {
+    data: Vec<T>, 
+    size: u32,
+    size_power: u32,
+    layout: Array3DLayout,
 }
 
-pub struct VoxelOctreeBuilder {
-    default_material: u16,
-    tree_depth: u32,
-    voxel_data: multiarray::Array3D<u16>,
+impl<T> Array3D2P<T> {
+    pub fn new(element: T, size: u32) -> Self where T: Clone {
+        Self::new_with_layout(element, size, Array3DLayout::Linear)
+    }
+
+    pub fn new_with_layout(element: T, size: u32, layout: Array3DLayout) -> Self where T: Clone {
+        let size_power: u32 = Self::exact_log(size);
+        let len = (size as usize).pow(3);
+
+        Array3D2P { data: vec![element; len], size, size_power, layout }
+    }
+
+    // Copies array into a cube large enough to hold it, cells outside of it are set to default
+    pub fn from_array(array: &multiarray::Array3D<T>, default: T, layout: Array3DLayout) -> Self where T: Clone {
+        let ext = array.extents();
+        let size = std::cmp::max(ext[0], std::cmp::max(ext[1], ext[2])).next_power_of_two() as u32;
+        let mut res = Self::new_with_layout(default, size, layout);
+
+        for z in 0..ext[2] {
+            for y in 0..ext[1] {
+                for x in 0..ext[0] {
+                    *res.get_mut(uvec3(x as u32, y as u32, z as u32)) = array[[x, y, z]].clone();
+                }
+            }
+        }
+
+        res
+    }
+
+    pub fn to_array(&self) -> multiarray::Array3D<T> where T: Clone {
+        let size = self.size as usize;
+        let mut array = multiarray::Array3D::new([size, size, size], self.data[0].clone());
+
+        for z in 0..size {
+            for y in 0..size {
+                for x in 0..size {
+                    array[[x, y, z]] = self.get(uvec3(x as u32, y as u32, z as u32)).clone();
+                }
+            }
+        }
+
+        array
+    }
+
+    // Returns a copy of the array stored in layout
+    pub fn to_layout(&self, layout: Array3DLayout) -> Self where T: Clone {
+        if layout == self.layout {
+            return Array3D2P { data: self.data.clone(), size: self.size, size_power: self.size_power, layout };
+        }
+
+        let mut data = self.data.clone();
+        for (i, element) in self.data.iter().enumerate() {
+            let pos = Self::position(self.layout, self.size_power, i);
+            data[Self::compute_index(layout, self.size_power, pos)] = element.clone();
+        }
+
+        Array3D2P { data, size: self.size, size_power: self.size_power, layout }
+    }
+
+    pub fn size(&self) -> u32 {
+        self.size
+    }
+
+    pub fn layout(&self) -> Array3DLayout {
+        self.layout
+    }
+
+    // The elements in storage order
+    pub fn as_slice(&self) -> &[T] {
+        &self.data
+    }
+
+    pub fn get(&self, pos: glam::UVec3) -> &T {
+        self.data.get(Self::compute_index(self.layout, self.size_power, pos)).unwrap()
+    }
+    
+    pub fn get_mut(&mut self, pos: glam::UVec3) -> &mut T {
+        self.data.get_mut(Self::compute_index(self.layout, self.size_power, pos)).unwrap()
+    }
+
+
+    /// # Safety
+    /// pos has to lie inside of the array.
+    pub unsafe fn get_unchecked(&self, pos: glam::UVec3) -> &T {
+        self.data.get_unchecked(Self::compute_index(self.layout, self.size_power, pos))
+    }
+
+    /// # Safety
+    /// pos has to lie inside of the array.
+    pub unsafe fn get_unchecked_mut(&mut self, pos: glam::UVec3) -> &mut T { 
+        self.data.get_unchecked_mut(Self::compute_index(self.layout, self.size_power, pos))
+    }
+
+    // Index of the element at pos in the storage
+    pub fn index_of(&self, pos: glam::UVec3) -> usize {
+        Self::compute_index(self.layout, self.size_power, pos)
+    }
+
+    fn compute_index(layout: Array3DLayout, power: u32, pos: glam:: UVec3) -> usize {
+        match layout {
+            Array3DLayout::Linear => ((pos.z as usize) << (power << 1)) | ((pos.y as usize) << power) | pos.x as usize,
+            Array3DLayout::Morton => crate::morton::morton_encode(pos) as usize,
+        }
+    }
+
+    fn position(layout: Array3DLayout, power: u32, index: usize) -> glam::UVec3 {
+        match layout {
+            Array3DLayout::Linear => {
+                let mask = (1 << power) - 1;
+                uvec3((index & mask) as u32, ((index >> power) & mask) as u32, (index >> (power << 1)) as u32)
+            }
+            Array3DLayout::Morton => crate::morton::morton_decode(index as u64),
+        }
+    }
+
+    fn exact_log(n: u32) -> u32 {
+        let pow : u32 = n.trailing_zeros();
+        assert! (n.leading_zeros() + pow == 31, "The given size was not a power of 2."); 
+        pow
+    }
+}
+
+// A growable buffer of Copy elements that frees its allocation when dropped.
+// Safe accessors are always bounds checked, the unchecked ones only in debug builds.
+pub struct UnmanagedByteBuffer<T: Copy = u16> {
+    data: std::ptr::NonNull<T>,
+    capacity: usize,
+    count: usize,
+}
+
+// The buffer owns its allocation the way a Vec<T> does and never hands the pointer to anyone
+// else, mutation needs &mut self. So it can move between or be shared by threads as far as T can.
+unsafe impl<T: Copy + Send> Send for UnmanagedByteBuffer<T> {}
+unsafe impl<T: Copy + Sync> Sync for UnmanagedByteBuffer<T> {}
+
+impl<T: Copy> UnmanagedByteBuffer<T> {
+    pub fn new() -> Self {
+        Self::new_with_capacity(0)
+    }
+
+    pub fn new_with_capacity(capacity: usize) -> Self {
+        assert!(std::mem::size_of::<T>() > 0, "Zero sized elements are not supported.");
+        let mut buffer = Self { data: std::ptr::NonNull::dangling(), capacity: 0, count: 0 };
+        buffer.reserve(capacity);
+        buffer
+    }
+
+    pub fn from_slice(values: &[T]) -> Self {
+        let mut buffer = Self::new_with_capacity(values.len());
+        buffer.extend_from_slice(values);
+        buffer
+    }
+
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    // Makes room for at least additional more elements, growing by doubling
+    pub fn reserve(&mut self, additional: usize) {
+        let required = self.count.checked_add(additional).expect("Buffer capacity overflow.");
+        if required <= self.capacity {
+            return;
+        }
+
+        let capacity = std::cmp::max(required, std::cmp::max(2 * self.capacity, 8));
+        let layout = std::alloc::Layout::array::<T>(capacity).expect("Buffer capacity overflow.");
+
+        let data = unsafe {
+            if self.capacity == 0 {
+                std::alloc::alloc(layout)
+            } else {
+                std::alloc::realloc(self.data.as_ptr() as *mut u8, Self::layout(self.capacity), layout.size())
+            }
+        };
+
+        self.data = match std::ptr::NonNull::new(data as *mut T) {
+            Some(data) => data,
+            None => std::alloc::handle_alloc_error(layout),
+        };
+        self.capacity = capacity;
+    }
+
+    // Appends an element and returns its position
+    pub fn add(&mut self, value: T) -> usize {
+        if self.count == self.capacity {
+            self.reserve(1);
+        }
+        unsafe { self.data.as_ptr().add(self.count).write(value) };
+        self.count += 1;
+        self.count - 1
+    }
+
+    pub fn extend_from_slice(&mut self, values: &[T]) {
+        self.reserve(values.len());
+        unsafe { std::ptr::copy_nonoverlapping(values.as_ptr(), self.data.as_ptr().add(self.count), values.len()) };
+        self.count += values.len();
+    }
+
+    pub fn get(&self, position: usize) -> T {
+        assert!(position < self.count, "Buffer position {} is out of bounds ({}).", position, self.count);
+        unsafe { self.get_unchecked(position) }
+    }
+
+    pub fn set(&mut self, position: usize, value: T) {
+        assert!(position < self.count, "Buffer position {} is out of bounds ({}).", position, self.count);
+        unsafe { self.set_unchecked(position, value) }
+    }
+
+    /// # Safety
+    /// position has to be below count().
+    pub unsafe fn get_unchecked(&self, position: usize) -> T {
+        debug_assert!(position < self.count);
+        self.data.as_ptr().add(position).read()
+    }
+
+    /// # Safety
+    /// position has to be below count().
+    pub unsafe fn set_unchecked(&mut self, position: usize, value: T) {
+        debug_assert!(position < self.count);
+        self.data.as_ptr().add(position).write(value)
+    }
+
+    pub fn as_ptr(&self) -> *const T {
+        self.data.as_ptr()
+    }
+
+    pub fn as_mut_ptr(&mut self) -> *mut T {
+        self.data.as_ptr()
+    }
+
+    pub fn truncate(&mut self, count: usize) {
+        self.count = std::cmp::min(self.count, count);
+    }
+
+    pub fn clear(&mut self) {
+        self.count = 0;
+    }
+
+    pub fn as_slice(&self) -> &[T] {
+        unsafe { std::slice::from_raw_parts(self.data.as_ptr(), self.count) }
+    }
+
+    pub fn as_mut_slice(&mut self) -> &mut [T] {
+        unsafe { std::slice::from_raw_parts_mut(self.data.as_ptr(), self.count) }
+    }
+
+    fn layout(capacity: usize) -> std::alloc::Layout {
+        std::alloc::Layout::array::<T>(capacity).unwrap()
+    }
+}
+
+impl<T: Copy> Default for UnmanagedByteBuffer<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: Copy> Clone for UnmanagedByteBuffer<T> {
+    fn clone(&self) -> Self {
+        Self::from_slice(self.as_slice())
+    }
+}
+
+impl<T: Copy> Drop for UnmanagedByteBuffer<T> {
+    fn drop(&mut self) {
+        if self.capacity > 0 {
+            unsafe { std::alloc::dealloc(self.data.as_ptr() as *mut u8, Self::layout(self.capacity)) };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::alloc::{GlobalAlloc, Layout, System};
+    use std::cell::Cell;
+
+    // Counts the bytes every thread holds, so tests running in parallel don't see each other
+    struct CountingAllocator;
+
+    thread_local! {
+        static ALLOCATED: Cell<isize> = Cell::new(0);
+    }
+
+    unsafe impl GlobalAlloc for CountingAllocator {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            let _ = ALLOCATED.try_with(|allocated| allocated.set(allocated.get() + layout.size() as isize));
+            System.alloc(layout)
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            let _ = ALLOCATED.try_with(|allocated| allocated.set(allocated.get() - layout.size() as isize));
+            System.dealloc(ptr, layout)
+        }
+
+        unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+            let _ = ALLOCATED.try_with(|allocated| allocated.set(allocated.get() + new_size as isize - layout.size() as isize));
+            System.realloc(ptr, layout, new_size)
+        }
+    }
+
+    #[global_allocator]
+    static GLOBAL: CountingAllocator = CountingAllocator;
+
+    fn allocated() -> isize {
+        ALLOCATED.with(|allocated| allocated.get())
+    }
+
+    #[test]
+    fn grows_and_keeps_its_elements() {
+        let mut buffer = UnmanagedByteBuffer::<u64>::new();
+        assert_eq!(buffer.capacity(), 0);
+        for i in 0..1000u64 {
+            assert_eq!(buffer.add(i * 3), i as usize);
+            assert!(buffer.capacity() >= buffer.count());
+            assert_eq!(buffer.as_ptr() as usize % std::mem::align_of::<u64>(), 0);
+        }
+        assert!(buffer.capacity() < 2 * 1000);
+        assert!((0..1000).all(|i| buffer.get(i) == i as u64 * 3));
+
+        buffer.extend_from_slice(&[7; 5000]);
+        assert_eq!(buffer.count(), 6000);
+        assert_eq!(buffer.get(999), 999 * 3);
+        assert_eq!(buffer.get(5999), 7);
+
+        buffer.truncate(10);
+        buffer.set(9, 1);
+        assert_eq!(buffer.as_slice(), &[0, 3, 6, 9, 12, 15, 18, 21, 24, 1]);
+
+        let capacity = buffer.capacity();
+        buffer.reserve(capacity - buffer.count());
+        assert_eq!(buffer.capacity(), capacity);
+    }
+
+    #[test]
+    fn clones_are_independent() {
+        let mut buffer = UnmanagedByteBuffer::from_slice(&[1u16, 2, 3]);
+        let clone = buffer.clone();
+        buffer.set(0, 9);
+        buffer.add(4);
+        assert_eq!(clone.as_slice(), &[1, 2, 3]);
+        assert_eq!(buffer.as_slice(), &[9, 2, 3, 4]);
+        assert_ne!(clone.as_ptr(), buffer.as_ptr());
+    }
+
+    #[test]
+    fn frees_its_allocation_when_dropped() {
+        let before = allocated();
+        {
+            let mut buffer = UnmanagedByteBuffer::<u32>::new_with_capacity(100);
+            assert_eq!(allocated() - before, 400);
+            buffer.extend_from_slice(&[0; 1000]);
+            let clone = buffer.clone();
+            assert!(allocated() - before >= 8000);
+            drop(clone);
+        }
+        assert_eq!(allocated(), before);
+
+        // Empty buffers never allocate
+        drop(UnmanagedByteBuffer::<u16>::new());
+        assert_eq!(allocated(), before);
+    }
+
+    #[test]
+    #[should_panic(expected = "out of bounds")]
+    fn get_checks_bounds() {
+        let mut buffer = UnmanagedByteBuffer::from_slice(&[1u16, 2, 3]);
+        buffer.truncate(2);
+        buffer.get(2);
+    }
+
+    #[test]
+    #[should_panic(expected = "out of bounds")]
+    fn set_checks_bounds() {
+        let mut buffer = UnmanagedByteBuffer::<u16>::new_with_capacity(4);
+        buffer.set(0, 1);
+    }
+
+    #[test]
+    fn octants_round_trip_through_offsets() {
+        for (index, octant) in VoxelOctant::iter_all().enumerate() {
+            assert_eq!(VoxelOctant::ALL[index], octant);
+            assert_eq!(VoxelOctant::from_index(index), octant);
+            assert_eq!(octant.index(), index);
+            assert_eq!(VoxelOctant::from_offset(octant.to_offset()), octant);
+            assert!(octant.to_offset().cmple(UVec3::ONE).all());
+            assert_eq!(VoxelOctant::from_offset(octant.to_offset() + UVec3::splat(6)), octant);
+            assert_eq!(VoxelOctant::containing(octant.to_offset() << 3, 3), octant);
+            assert_eq!(octant.opposite().to_offset(), UVec3::ONE - octant.to_offset());
+        }
+    }
+
+    #[test]
+    fn octants_on_faces() {
+        for octant in VoxelOctant::iter_all() {
+            for direction in Direction::FACES {
+                let axis = direction.axis() as usize;
+                let offset = octant.to_offset();
+                let positive = offset[axis] == 1;
+                assert_eq!(octant.is_positive(direction.axis()), positive);
+                assert_eq!(octant.touches_face(direction), positive == direction.is_positive());
+                assert_eq!(VoxelOctant::on_face(direction).contains(&octant), octant.touches_face(direction), "{:?} {:?}", octant, direction);
+
+                let (neighbor, across) = match octant.face_neighbor(direction) {
+                    OctantNeighbor::Sibling(neighbor) => (neighbor, false),
+                    OctantNeighbor::AcrossParent(neighbor) => (neighbor, true),
+                };
+                // Exactly the bit of the direction's axis differs
+                assert_eq!(neighbor ^ octant, VoxelOctant::axis_bit(direction.axis()));
+                assert_eq!(neighbor.to_offset()[axis], 1 - offset[axis]);
+                assert_eq!(across, octant.touches_face(direction));
+
+                // Stepping out of the parent at 2..4 of a grid of 2x2x2 parents agrees
+                let pos = (offset + UVec3::splat(2)).as_ivec3() + direction.to_ivec3();
+                let inside = pos.cmpge(IVec3::splat(2)).all() && pos.cmple(IVec3::splat(3)).all();
+                assert_eq!(!across, inside);
+                assert_eq!(VoxelOctant::from_offset(pos.as_uvec3()), neighbor);
+            }
+        }
+
+        for direction in Direction::FACES {
+            let face = VoxelOctant::on_face(direction);
+            assert!(face.windows(2).all(|pair| pair[0].index() < pair[1].index()));
+            assert!(face.iter().all(|octant| octant.touches_face(direction)));
+        }
+    }
+
+    #[test]
+    fn directions() {
+        for (index, direction) in Direction::FACES.into_iter().enumerate() {
+            assert_eq!(Direction::from_face_index(index), direction);
+            assert_eq!(direction.face_index(), index);
+            assert_eq!(direction.faces().collect::<Vec<_>>(), vec![direction]);
+            assert_eq!(direction.opposite().opposite(), direction);
+            assert_eq!(direction.opposite().axis(), direction.axis());
+            assert_ne!(direction.opposite().is_positive(), direction.is_positive());
+            assert_eq!(direction.opposite().to_ivec3(), -direction.to_ivec3());
+
+            let step = direction.to_ivec3();
+            assert_eq!(step.abs().dot(IVec3::ONE), 1);
+            assert_eq!(step[direction.axis() as usize], if direction.is_positive() { 1 } else { -1 });
+        }
+
+        assert_eq!(Direction::all().faces().collect::<Vec<_>>(), Direction::FACES.to_vec());
+        assert_eq!((Direction::UP | Direction::LEFT).faces().collect::<Vec<_>>(), vec![Direction::LEFT, Direction::UP]);
+        assert_eq!(Direction::NONE.faces().count(), 0);
+        assert_eq!([Direction::LEFT.axis(), Direction::DOWN.axis(), Direction::FRONT.axis()], [Axis::X, Axis::Y, Axis::Z]);
+    }
 }
\ No newline at end of file
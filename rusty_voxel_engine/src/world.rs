@@ -0,0 +1,253 @@
+// world.rs
+use crate::types::*;
+
+use glam::{IVec3, UVec3};
+use std::collections::HashMap;
+
+// Called by a VoxelWorld when chunks enter or leave memory
+pub trait VoxelWorldHooks<T: VoxelPayload> {
+    // Provides the content of a chunk that is about to be loaded, None fills it with the
+    // default material of the world
+    fn load(&mut self, _coord: IVec3) -> Option<VoxelOctree<T>> {
+        None
+    }
+
+    // Receives a chunk that was just unloaded, e.g. to save it
+    fn unload(&mut self, _coord: IVec3, _chunk: VoxelOctree<T>) {}
+}
+
+// A world without hooks, chunks start out empty and are dropped on unload
+impl<T: VoxelPayload> VoxelWorldHooks<T> for () {}
+
+// An unbounded world made of cubic chunks, each one a VoxelOctree of chunk_depth levels.
+// Voxel and chunk coordinates are signed, the chunk at coord covers the voxels from
+// coord << chunk_depth up to but excluding (coord + 1) << chunk_depth.
+pub struct VoxelWorld<T: VoxelPayload = u16, H: VoxelWorldHooks<T> = ()> {
+    chunk_depth: u32,
+    default_material: T,
+    chunks: HashMap<IVec3, VoxelOctree<T>>,
+    hooks: H,
+}
+
+impl<T: VoxelPayload> VoxelWorld<T> {
+    pub fn new(chunk_depth: u32, default_material: T) -> Self {
+        Self::with_hooks(chunk_depth, default_material, ())
+    }
+}
+
+impl<T: VoxelPayload, H: VoxelWorldHooks<T>> VoxelWorld<T, H> {
+    pub fn with_hooks(chunk_depth: u32, default_material: T, hooks: H) -> Self {
+        // Voxel coordinates are i32, a chunk has to span less than half of their range
+        assert!(chunk_depth > 0 && chunk_depth < 31, "The chunk depth has to be between 1 and 30.");
+        Self { chunk_depth, default_material, chunks: HashMap::new(), hooks }
+    }
+
+    pub fn chunk_depth(&self) -> u32 {
+        self.chunk_depth
+    }
+
+    // Edge length of a chunk in voxels
+    pub fn chunk_size(&self) -> u32 {
+        1 << self.chunk_depth
+    }
+
+    pub fn default_material(&self) -> T {
+        self.default_material
+    }
+
+    pub fn hooks(&self) -> &H {
+        &self.hooks
+    }
+
+    pub fn hooks_mut(&mut self) -> &mut H {
+        &mut self.hooks
+    }
+
+    // Returns the coordinate of the chunk containing pos. The arithmetic shift rounds towards
+    // negative infinity, so the voxel at -1 lies in chunk -1 rather than chunk 0.
+    pub fn chunk_coord(&self, pos: IVec3) -> IVec3 {
+        pos >> self.chunk_depth as i32
+    }
+
+    // Returns the position of pos inside of its chunk
+    pub fn local_pos(&self, pos: IVec3) -> UVec3 {
+        (pos & IVec3::splat((1 << self.chunk_depth) - 1)).as_uvec3()
+    }
+
+    // Returns the world position of the voxel at local inside of the chunk at coord
+    pub fn world_pos(&self, coord: IVec3, local: UVec3) -> IVec3 {
+        (coord << self.chunk_depth as i32) + local.as_ivec3()
+    }
+
+    // Returns the material at pos. A chunk that isn't loaded is asked for from the hooks, but
+    // it only stays loaded if they provide it, so reading doesn't fill up with empty chunks.
+    pub fn get(&mut self, pos: IVec3) -> T {
+        let coord = self.chunk_coord(pos);
+        if !self.is_loaded(coord) {
+            match self.hooks.load(coord) {
+                Some(chunk) => {
+                    assert!(chunk.tree_depth() == self.chunk_depth, "The chunk at {} has a depth of {} instead of {}.", coord, chunk.tree_depth(), self.chunk_depth);
+                    self.chunks.insert(coord, chunk);
+                }
+                None => return self.default_material,
+            }
+        }
+        self.chunks[&coord].get(self.local_pos(pos))
+    }
+
+    // Returns the material at pos without loading anything, voxels of chunks that aren't loaded
+    // read as the default material even when the hooks hold them
+    pub fn get_loaded(&self, pos: IVec3) -> T {
+        match self.chunks.get(&self.chunk_coord(pos)) {
+            Some(chunk) => chunk.get(self.local_pos(pos)),
+            None => self.default_material,
+        }
+    }
+
+    // Sets the material at pos, loading its chunk if needed
+    pub fn set(&mut self, pos: IVec3, material: T) {
+        let local = self.local_pos(pos);
+        self.load_chunk(self.chunk_coord(pos)).set(local, material);
+    }
+
+    // Fills the box between min and max (both inclusive) with material, loading every chunk
+    // it touches
+    pub fn fill(&mut self, min: IVec3, max: IVec3, material: T) {
+        assert!(min.cmple(max).all(), "The box is empty.");
+
+        let (chunk_min, chunk_max) = (self.chunk_coord(min), self.chunk_coord(max));
+        for z in chunk_min.z..=chunk_max.z {
+            for y in chunk_min.y..=chunk_max.y {
+                for x in chunk_min.x..=chunk_max.x {
+                    let coord = IVec3::new(x, y, z);
+                    let chunk_start = self.world_pos(coord, UVec3::ZERO);
+                    let local_min = self.local_pos(min.max(chunk_start));
+                    let local_max = self.local_pos(max.min(chunk_start + IVec3::splat(self.chunk_size() as i32 - 1)));
+                    self.load_chunk(coord).fill(local_min, local_max, material);
+                }
+            }
+        }
+    }
+
+    pub fn is_loaded(&self, coord: IVec3) -> bool {
+        self.chunks.contains_key(&coord)
+    }
+
+    pub fn chunk(&self, coord: IVec3) -> Option<&VoxelOctree<T>> {
+        self.chunks.get(&coord)
+    }
+
+    pub fn chunk_mut(&mut self, coord: IVec3) -> Option<&mut VoxelOctree<T>> {
+        self.chunks.get_mut(&coord)
+    }
+
+    // Iterates over the loaded chunks and their coordinates in no particular order
+    pub fn chunks(&self) -> impl Iterator<Item = (IVec3, &VoxelOctree<T>)> {
+        self.chunks.iter().map(|(coord, chunk)| (*coord, chunk))
+    }
+
+    pub fn loaded_len(&self) -> usize {
+        self.chunks.len()
+    }
+
+    // Returns the chunk at coord, asking the hooks for it when it isn't loaded yet
+    pub fn load_chunk(&mut self, coord: IVec3) -> &mut VoxelOctree<T> {
+        let (hooks, chunk_depth, default_material) = (&mut self.hooks, self.chunk_depth, self.default_material);
+        self.chunks.entry(coord).or_insert_with(|| {
+            let chunk = hooks.load(coord).unwrap_or_else(|| VoxelOctree::filled(chunk_depth, default_material));
+            assert!(chunk.tree_depth() == chunk_depth, "The chunk at {} has a depth of {} instead of {}.", coord, chunk.tree_depth(), chunk_depth);
+            chunk
+        })
+    }
+
+    // Puts chunk at coord, a chunk already loaded there is unloaded first
+    pub fn insert_chunk(&mut self, coord: IVec3, chunk: VoxelOctree<T>) {
+        assert!(chunk.tree_depth() == self.chunk_depth, "The chunk at {} has a depth of {} instead of {}.", coord, chunk.tree_depth(), self.chunk_depth);
+        self.unload_chunk(coord);
+        self.chunks.insert(coord, chunk);
+    }
+
+    // Hands the chunk at coord to the hooks, returns whether it was loaded
+    pub fn unload_chunk(&mut self, coord: IVec3) -> bool {
+        match self.chunks.remove(&coord) {
+            Some(chunk) => {
+                self.hooks.unload(coord, chunk);
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Unloads every chunk for which keep returns false
+    pub fn unload_where(&mut self, mut keep: impl FnMut(IVec3, &VoxelOctree<T>) -> bool) {
+        let coords: Vec<IVec3> = self.chunks.iter().filter(|(coord, chunk)| !keep(**coord, chunk)).map(|(coord, _)| *coord).collect();
+        for coord in coords {
+            self.unload_chunk(coord);
+        }
+    }
+
+    pub fn unload_all(&mut self) {
+        self.unload_where(|_, _| false);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Keeps unloaded chunks around and counts how often chunks get loaded
+    #[derive(Default)]
+    struct Store {
+        saved: HashMap<IVec3, VoxelOctree>,
+        loads: usize,
+    }
+
+    impl VoxelWorldHooks<u16> for Store {
+        fn load(&mut self, coord: IVec3) -> Option<VoxelOctree> {
+            self.loads += 1;
+            self.saved.remove(&coord)
+        }
+
+        fn unload(&mut self, coord: IVec3, chunk: VoxelOctree) {
+            self.saved.insert(coord, chunk);
+        }
+    }
+
+    #[test]
+    fn chunk_coords_round_towards_negative_infinity() {
+        let world = VoxelWorld::new(4, 0u16);
+        assert_eq!(world.chunk_coord(IVec3::new(-1, -16, -17)), IVec3::new(-1, -1, -2));
+        assert_eq!(world.local_pos(IVec3::new(-1, -16, -17)), UVec3::new(15, 0, 15));
+        assert_eq!(world.world_pos(IVec3::new(-1, -1, -2), UVec3::new(15, 0, 15)), IVec3::new(-1, -16, -17));
+    }
+
+    #[test]
+    fn unloaded_chunks_read_back_through_hooks() {
+        let mut world = VoxelWorld::with_hooks(3, 0u16, Store::default());
+        world.set(IVec3::new(-1, -1, -1), 5);
+        world.set(IVec3::new(8, -9, 0), 6);
+        world.fill(IVec3::new(-10, 2, -3), IVec3::new(3, 4, 3), 7);
+        assert_eq!(world.get(IVec3::new(-1, -1, -1)), 5);
+
+        world.unload_all();
+        assert_eq!(world.loaded_len(), 0);
+        assert_eq!(world.hooks().saved.len(), 8);
+
+        // Reading without loading doesn't see the saved chunks
+        assert_eq!(world.get_loaded(IVec3::new(-1, -1, -1)), 0);
+        assert_eq!(world.hooks().loads, 8);
+
+        assert_eq!(world.get(IVec3::new(-1, -1, -1)), 5);
+        assert_eq!(world.get(IVec3::new(-2, -1, -1)), 0);
+        assert_eq!(world.get(IVec3::new(8, -9, 0)), 6);
+        assert_eq!(world.get(IVec3::new(-10, 3, -3)), 7);
+        assert_eq!(world.get(IVec3::new(-11, 3, -3)), 0);
+        assert_eq!(world.get_loaded(IVec3::new(-1, -1, -1)), 5);
+        assert!(world.is_loaded(IVec3::new(-1, -1, -1)));
+
+        // Chunks the hooks don't have aren't kept around by reads
+        assert_eq!(world.get(IVec3::new(-100, 50, 1000)), 0);
+        assert!(!world.is_loaded(world.chunk_coord(IVec3::new(-100, 50, 1000))));
+        assert_eq!(world.loaded_len(), 3);
+    }
+}
\ No newline at end of file